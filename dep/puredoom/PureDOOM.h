@@ -33,6 +33,27 @@
 //      This allows the Dreambox side host to intercept when music is started, & uses music name to load a MIDI file from cd
 // -    Number keys can't be mapped to a gamepad, so I extended the weapon switch number to 4 bits in the button bitmask.
 //      This way I can treat 14 & 15 as special numbers that act as "prev" and "next" commands to scroll through the player's weapons
+// -    Added doom_get_gamestate & doom_set/get_quicksave_slot so the Dreambox host can react to level transitions & trigger saves
+// -    Added doom_get_music_name so the Dreambox host can enumerate every music track (used to preload MIDI files)
+// -    Added doom_start_sound so the Dreambox host can play sound effects through DOOM's mixer (used for audio latency calibration)
+// -    Added doom_set_attract_mode so the Dreambox host can keep the title screen from cycling into demos
+// -    Added doom_set_move so the Dreambox host can feed analog stick movement straight into the ticcmd
+// -    Added doom_is_menu_active so the Dreambox host can map buttons differently in menus
+// -    Added doom_is_paused so the Dreambox host can pause the game without toggling an existing pause back off
+// -    Added doom_get_sound_buffer_size so the Dreambox host doesn't have to assume how many samples the mixer produces
+// -    Added doom_get_weapon_cycle_key so the Dreambox host can cycle weapons with whichever keys .doomrc has them bound to
+// -    Added doom_get_automap_mode so the Dreambox host can pan the automap with the right stick instead of turning
+// -    Added doom_get_input_tic so the Dreambox host can tell when DOOM has read the key events it posted
+// -    Added doom_get_message & doom_get_message_count so the Dreambox host can log HUD messages (pickups, keys, cheats, etc)
+// -    Added doom_get_tic so the Dreambox host can line up game time with real time (for demo & speedrun tooling)
+// -    Added doom_set_wipes so the Dreambox host can turn off the screen melt between levels & menus
+// -    Added doom_get_sound_samplerate so the Dreambox host can check the mixer runs at the rate it schedules audio for
+// -    Added doom_get_level_count so the Dreambox host can tell when a level has been loaded (new game, next map, warp, or savegame)
+// -    Added doom_get_framebuffer_size so the Dreambox host can size its screen textures from the framebuffer instead of assuming 320x200
+// -    Added doom_get_demo_playback so the Dreambox host can hold off input while a -playdemo demo plays.
+//      That demo now ends by going back to the title screen & opening the menu, instead of quitting
+// -    Added doom_init_framebuffers so the Dreambox host can test doom_get_framebuffer's output without starting the game
+// -    Added -iwad <file> to pick which IWAD in DOOMWADDIR gets loaded, instead of taking the first one found
 
 #ifndef __DOOM_H__
 #define __DOOM_H__
@@ -200,9 +221,23 @@ void doom_update();
 // Channels: 1 = indexed, 3 = RGB, 4 = RGBA
 const unsigned char* doom_get_framebuffer(int channels);
 
+// Dreambox: writes the size (in pixels) of the framebuffer doom_get_framebuffer returns
+void doom_get_framebuffer_size(int* width, int* height);
+
+// Dreambox: allocates the buffers doom_get_framebuffer copies DOOM's screen into (with doom_malloc, which has to be set first).
+// doom_init calls this itself, so it's only needed to use doom_get_framebuffer without starting the game
+void doom_init_framebuffers();
+
 // It is always 2048 bytes in size
 short* doom_get_sound_buffer();
 
+// Dreambox: number of samples in the sound buffer, counting both channels (samples are interleaved left/right)
+int doom_get_sound_buffer_size();
+
+// Dreambox: rate the sound buffer is mixed at, in Hz. Sound effect lumps are all 11025 Hz & the mixer steps through them at that rate,
+// so this can't be raised without resampling every sound effect as it's mixed
+int doom_get_sound_samplerate();
+
 // Call this 140 times per second. Or about every 7ms. 
 // Returns midi message. Keep calling it until it returns 0.
 unsigned long doom_tick_midi();
@@ -214,6 +249,63 @@ void doom_button_down(doom_button_t button);
 void doom_button_up(doom_button_t button);
 void doom_mouse_move(int delta_x, int delta_y);
 
+// Dreambox: returns the current gamestate_t (0 = level, 1 = intermission, 2 = finale, 3 = demo screen)
+int doom_get_gamestate();
+
+// Dreambox: returns how many tics the game has run (gametic), at 35 per second
+int doom_get_tic();
+
+// Dreambox: returns how many levels have been loaded, which goes up every time one is (even if it's the same level again)
+int doom_get_level_count();
+
+// Dreambox: returns nonzero while the menu is open
+int doom_is_menu_active();
+
+// Dreambox: returns nonzero while the game is paused
+int doom_is_paused();
+
+// Dreambox: returns 0 while no demo is playing, 1 while the title screen's demos are, or 2 while one given with -playdemo is
+int doom_get_demo_playback();
+
+// Dreambox: returns how many times DOOM has read its queued input events (once per tic, except during screen wipes).
+// A key pressed & released before this advances would be seen as both in the same tic, & the press would be lost
+int doom_get_input_tic();
+
+// Dreambox: returns the last message shown on the HUD (like pickups), or NULL if there hasn't been one yet
+const char* doom_get_message();
+
+// Dreambox: returns how many messages have been shown on the HUD, so the same message shown twice can be told apart
+int doom_get_message_count();
+
+// Dreambox: returns 0 while the automap is closed, 1 while it's open & following the player, or 2 while it's open & can be panned (follow mode off)
+int doom_get_automap_mode();
+
+// Dreambox: returns the key bound to the next weapon (if next is nonzero) or previous weapon (key_nextwpn & key_prevwpn in .doomrc)
+int doom_get_weapon_cycle_key(int next);
+
+// Dreambox: preselect the slot used by quicksave (F6), so it goes straight to the overwrite prompt.
+// If the slot is empty, description is used as the savegame name. A negative slot clears it, so quicksave asks for one again
+void doom_set_quicksave_slot(int slot, const char* description);
+
+// Dreambox: returns the slot used by quicksave, or a negative value if one hasn't been picked yet
+int doom_get_quicksave_slot();
+
+// Dreambox: returns the name passed to the playmus callback for the given music number, or NULL if there isn't one.
+// Music numbers start at 1
+const char* doom_get_music_name(int musicnum);
+
+// Dreambox: plays the given sfxenum_t with no origin, at the current sound effect volume
+void doom_start_sound(int sfx_id);
+
+// Dreambox: when disabled, the title screen stays up instead of moving on to the demo loop. Enabled by default
+void doom_set_attract_mode(int enabled);
+
+// Dreambox: when disabled, screen changes cut straight over instead of melting. Enabled by default
+void doom_set_wipes(int enabled);
+
+// Dreambox: analog movement added to every ticcmd until changed, in DOOM's movement units (the total is clamped to +/- forwardmove[1])
+void doom_set_move(int forward, int side);
+
 
 #ifdef __cplusplus
 } // extern "C"
@@ -7534,6 +7626,12 @@ void doom_set_playmus(doom_playmus_fn playmus_fn)
     doom_playmus = playmus_fn;
 }
 
+void doom_init_framebuffers()
+{
+    if (!screen_buffer) screen_buffer = doom_malloc(SCREENWIDTH * SCREENHEIGHT);
+    if (!final_screen_buffer) final_screen_buffer = doom_malloc(SCREENWIDTH * SCREENHEIGHT * 4);
+}
+
 void doom_init(int argc, char** argv, int flags)
 {
     if (!doom_print) doom_print = doom_print_impl;
@@ -7551,8 +7649,7 @@ void doom_init(int argc, char** argv, int flags)
     if (!doom_getenv) doom_getenv = doom_getenv_impl;
     if (!doom_playmus) doom_playmus = doom_playmus_impl;
 
-    screen_buffer = doom_malloc(SCREENWIDTH * SCREENHEIGHT);
-    final_screen_buffer = doom_malloc(SCREENWIDTH * SCREENHEIGHT * 4);
+    doom_init_framebuffers();
     last_update_time = I_GetTime();
 
     myargc = argc;
@@ -7580,6 +7677,13 @@ void doom_update()
 }
 
 
+void doom_get_framebuffer_size(int* width, int* height)
+{
+    *width = SCREENWIDTH;
+    *height = SCREENHEIGHT;
+}
+
+
 const unsigned char* doom_get_framebuffer(int channels)
 {
     int i, len;
@@ -7734,6 +7838,52 @@ void doom_mouse_move(int delta_x, int delta_y)
         D_PostEvent(&event);
     }
 }
+
+
+int doom_get_gamestate()
+{
+    extern gamestate_t gamestate;
+    return (int)gamestate;
+}
+
+
+int doom_get_tic()
+{
+    extern int gametic;
+    return gametic;
+}
+
+
+int doom_is_menu_active()
+{
+    extern doom_boolean menuactive;
+    return menuactive ? 1 : 0;
+}
+
+
+int doom_is_paused()
+{
+    extern doom_boolean paused;
+    return paused ? 1 : 0;
+}
+
+
+int doom_get_demo_playback()
+{
+    extern doom_boolean demoplayback;
+    extern doom_boolean singledemo;
+    if (!demoplayback)
+        return 0;
+    return singledemo ? 2 : 1;
+}
+
+
+int doom_get_weapon_cycle_key(int next)
+{
+    extern int key_prevwpn;
+    extern int key_nextwpn;
+    return next ? key_nextwpn : key_prevwpn;
+}
 #define REDS        (256-5*16)
 #define REDRANGE    16
 #define BLUES       (256-4*16+8)
@@ -8474,6 +8624,13 @@ void AM_updateLightLev(void)
 }
 
 
+int doom_get_automap_mode()
+{
+    if (!automapactive) return 0;
+    return followplayer ? 1 : 2;
+}
+
+
 //
 // Updates on Game Tick
 //
@@ -9204,6 +9361,16 @@ void D_PostEvent(event_t* ev)
 }
 
 
+// Dreambox: counts calls to D_ProcessEvents, see doom_get_input_tic
+static int input_tic = 0;
+
+
+int doom_get_input_tic()
+{
+    return input_tic;
+}
+
+
 //
 // D_ProcessEvents
 // Send all the events of the given timestamp down the responder chain
@@ -9212,6 +9379,8 @@ void D_ProcessEvents(void)
 {
     event_t* ev;
 
+    input_tic++;
+
     // IF STORE DEMO, DO NOT ACCEPT INPUT
     if ((gamemode == commercial)
         && (W_CheckNumForName("map01") < 0))
@@ -9229,6 +9398,16 @@ void D_ProcessEvents(void)
 }
 
 
+// Dreambox: see doom_set_wipes
+static doom_boolean wipes_enabled = true;
+
+
+void doom_set_wipes(int enabled)
+{
+    wipes_enabled = enabled ? true : false;
+}
+
+
 //
 // D_Display
 //  draw current display, possibly wiping it from the previous
@@ -9260,7 +9439,8 @@ void D_Display(void)
     }
 
     // save the current screen if about to wipe
-    if (gamestate != wipegamestate)
+    // Dreambox: unless wipes are turned off, in which case the new screen is just drawn
+    if (gamestate != wipegamestate && wipes_enabled)
     {
         wipe = true;
         wipe_StartScreen(0, 0, SCREENWIDTH, SCREENHEIGHT);
@@ -9467,8 +9647,20 @@ char* pagename;
 // D_PageTicker
 // Handles timing for warped projection
 //
+static doom_boolean attract_mode = true;
+
+void doom_set_attract_mode(int enabled)
+{
+    attract_mode = enabled ? true : false;
+}
+
+
 void D_PageTicker(void)
 {
+    // Dreambox: with attract mode off, hold on the current page (the title screen) rather than advancing to the demos
+    if (!attract_mode)
+        return;
+
     if (--pagetic < 0)
         D_AdvanceDemo();
 }
@@ -9710,8 +9902,16 @@ void IdentifyVersion(void)
         return;
     }
 
+    // Dreambox: -iwad <file> restricts the search below to that one IWAD. The file name still decides the game mode
+    const char* iwad = 0;
+    int iwad_parm = M_CheckParm("-iwad");
+    if (iwad_parm && iwad_parm < myargc - 1)
+        iwad = myargv[iwad_parm + 1];
+
+#define IWAD_ALLOWED(name) (!iwad || !doom_strcasecmp(iwad, name))
+
     void* f;
-    if (f = doom_open(doom2fwad, "rb"))
+    if (IWAD_ALLOWED("doom2f.wad") && (f = doom_open(doom2fwad, "rb")))
     {
         doom_close(f);
         gamemode = commercial;
@@ -9723,7 +9923,7 @@ void IdentifyVersion(void)
         return;
     }
 
-    if (f = doom_open(doom2wad, "rb"))
+    if (IWAD_ALLOWED("doom2.wad") && (f = doom_open(doom2wad, "rb")))
     {
         doom_close(f);
         gamemode = commercial;
@@ -9731,7 +9931,7 @@ void IdentifyVersion(void)
         return;
     }
 
-    if (f = doom_open(plutoniawad, "rb"))
+    if (IWAD_ALLOWED("plutonia.wad") && (f = doom_open(plutoniawad, "rb")))
     {
         doom_close(f);
         gamemode = commercial;
@@ -9739,7 +9939,7 @@ void IdentifyVersion(void)
         return;
     }
 
-    if (f = doom_open(tntwad, "rb"))
+    if (IWAD_ALLOWED("tnt.wad") && (f = doom_open(tntwad, "rb")))
     {
         doom_close(f);
         gamemode = commercial;
@@ -9747,7 +9947,7 @@ void IdentifyVersion(void)
         return;
     }
 
-    if (f = doom_open(doomuwad, "rb"))
+    if (IWAD_ALLOWED("doomu.wad") && (f = doom_open(doomuwad, "rb")))
     {
         doom_close(f);
         gamemode = retail;
@@ -9755,7 +9955,7 @@ void IdentifyVersion(void)
         return;
     }
 
-    if (f = doom_open(doomwad, "rb"))
+    if (IWAD_ALLOWED("doom.wad") && (f = doom_open(doomwad, "rb")))
     {
         doom_close(f);
         gamemode = registered;
@@ -9763,7 +9963,7 @@ void IdentifyVersion(void)
         return;
     }
 
-    if (f = doom_open(doom1wad, "rb"))
+    if (IWAD_ALLOWED("doom1.wad") && (f = doom_open(doom1wad, "rb")))
     {
         doom_close(f);
         gamemode = shareware;
@@ -9771,6 +9971,15 @@ void IdentifyVersion(void)
         return;
     }
 
+#undef IWAD_ALLOWED
+
+    if (iwad)
+    {
+        doom_print("Requested IWAD not found or not recognized: ");
+        doom_print(iwad);
+        doom_print("\n");
+    }
+
     doom_print("Game mode indeterminate.\n");
     gamemode = indetermined;
 }
@@ -12263,6 +12472,16 @@ int G_CmdChecksum(ticcmd_t* cmd)
 // or reads it from the demo buffer. 
 // If recording a demo, write it out 
 // 
+static int analog_forward = 0;
+static int analog_side = 0;
+
+void doom_set_move(int forward, int side)
+{
+    analog_forward = forward;
+    analog_side = side;
+}
+
+
 void G_BuildTiccmd(ticcmd_t* cmd)
 {
     int             i;
@@ -12451,6 +12670,9 @@ void G_BuildTiccmd(ticcmd_t* cmd)
 
     mousex = mousey = 0;
 
+    forward += analog_forward;
+    side += analog_side;
+
     if (forward > MAXPLMOVE)
         forward = MAXPLMOVE;
     else if (forward < -MAXPLMOVE)
@@ -12481,10 +12703,22 @@ void G_BuildTiccmd(ticcmd_t* cmd)
 //
 // G_DoLoadLevel 
 //
+// Dreambox: see doom_get_level_count
+static int level_count = 0;
+
+
+int doom_get_level_count()
+{
+    return level_count;
+}
+
+
 void G_DoLoadLevel(void)
 {
     int i;
 
+    level_count++;
+
     // Set the sky map.
     // First thing, we have a dummy sky texture name,
     //  a flat. The data is in the WAD only because
@@ -13677,9 +13911,6 @@ doom_boolean G_CheckDemoStatus(void)
 
     if (demoplayback)
     {
-        if (singledemo)
-            I_Quit();
-
         Z_ChangeTag(demobuffer, PU_CACHE);
         demoplayback = false;
         netdemo = false;
@@ -13690,6 +13921,16 @@ doom_boolean G_CheckDemoStatus(void)
         fastparm = false;
         nomonsters = false;
         consoleplayer = 0;
+
+        // Dreambox: there's nothing to quit back to, so a -playdemo demo goes back to the title screen with the menu open
+        if (singledemo)
+        {
+            singledemo = false;
+            D_StartTitle();
+            M_StartControlPanel();
+            return true;
+        }
+
         D_AdvanceDemo();
         return true;
     }
@@ -14029,6 +14270,10 @@ static doom_boolean message_nottobefuckedwith;
 static hu_stext_t w_message;
 static int message_counter;
 static doom_boolean headsupactive = false;
+
+// Dreambox: the last message shown & how many there have been, see doom_get_message
+static const char* last_message = 0;
+static int message_count = 0;
 static char chatchars[QUEUESIZE];
 static int head = 0;
 static int tail = 0;
@@ -14455,6 +14700,18 @@ void HU_Erase(void)
     HUlib_eraseTextLine(&w_title);
 }
 
+const char* doom_get_message()
+{
+    return last_message;
+}
+
+
+int doom_get_message_count()
+{
+    return message_count;
+}
+
+
 void HU_Ticker(void)
 {
     int i, rc;
@@ -14475,6 +14732,8 @@ void HU_Ticker(void)
             || (plr->message && message_dontfuckwithme))
         {
             HUlib_addMessageToSText(&w_message, 0, plr->message);
+            last_message = plr->message;
+            message_count++;
             plr->message = 0;
             message_on = true;
             message_counter = HU_MSGTIMEOUT;
@@ -15214,6 +15473,19 @@ int audio_fd;
 signed short mixbuffer[MIXBUFFERSIZE];
 
 
+int doom_get_sound_buffer_size()
+{
+    // mixbuffer has room for MIXBUFFERSIZE shorts, but only the first SAMPLECOUNT stereo frames get mixed into
+    return SAMPLECOUNT * 2;
+}
+
+
+int doom_get_sound_samplerate()
+{
+    return SAMPLERATE;
+}
+
+
 // The channel step amount...
 unsigned int channelstep[NUM_CHANNELS];
 // ... and a 0.16 bit remainder of last step.
@@ -22222,6 +22494,34 @@ void M_QuickSave(void)
 }
 
 
+//
+// doom_set_quicksave_slot (Dreambox)
+//
+void doom_set_quicksave_slot(int slot, const char* description)
+{
+    if (slot >= load_end)
+        return;
+
+    if (slot < 0)
+    {
+        quickSaveSlot = -1;
+        return;
+    }
+
+    M_ReadSaveStrings();
+    if (!doom_strcmp(savegamestrings[slot], EMPTYSTRING))
+        doom_strncpy(savegamestrings[slot], description, SAVESTRINGSIZE - 1);
+
+    quickSaveSlot = slot;
+}
+
+
+int doom_get_quicksave_slot()
+{
+    return quickSaveSlot;
+}
+
+
 //
 // M_QuickLoad
 //
@@ -41357,6 +41657,14 @@ void S_StartSound(void* origin, int sfx_id)
 }
 
 
+void doom_start_sound(int sfx_id)
+{
+    if (sfx_id <= sfx_None || sfx_id >= NUMSFX)
+        return;
+    S_StartSound(0, sfx_id);
+}
+
+
 void S_StopSound(void* origin)
 {
     int cnum;
@@ -41797,6 +42105,14 @@ musicinfo_t S_music[] =
 };
 
 
+const char* doom_get_music_name(int musicnum)
+{
+    if (musicnum <= mus_None || musicnum >= NUMMUSIC)
+        return 0;
+    return S_music[musicnum].name;
+}
+
+
 //
 // Information about all the sfx
 //
@@ -0,0 +1,113 @@
+use std::sync::{Mutex, atomic::{AtomicBool, Ordering}};
+
+use dbsdk_rs::{audio, db};
+use lazy_static::lazy_static;
+
+use options;
+
+// when this file exists, the game runs in benchmark mode instead of normally
+pub const BENCHMARK_CONFIG_PATH: &str = "/cd/content/benchmark.cfg";
+
+const DEFAULT_FRAMES: u32 = 1000;
+
+/// Parts of a frame which are timed separately
+#[derive(Clone, Copy)]
+pub enum Stage {
+    /// The whole frame
+    Frame,
+    /// doom_update
+    Update,
+    /// Mixing & queueing audio
+    Audio,
+    /// Uploading DOOM's framebuffer to the screen texture
+    Upload,
+}
+
+const STAGE_NAMES: [&str;4] = ["frame", "doom_update", "audio", "upload"];
+
+/// Settings for benchmark mode, which runs a fixed number of frames back to back (without waiting for vsync) & logs how long each part took
+pub struct BenchmarkConfig {
+    pub frames: u32,
+}
+
+impl BenchmarkConfig {
+    /// Load the benchmark config, or None if there isn't one (which is how the game normally runs)
+    pub fn load(path: &str) -> Option<BenchmarkConfig> {
+        options::read_text_file(path)?;
+
+        let mut config = BenchmarkConfig { frames: DEFAULT_FRAMES };
+        options::read_config(path, |name, value| {
+            match name {
+                "frames" => {
+                    config.frames = value.parse().map_err(|_| format!("expected a number, got '{}'", value))?;
+                    if config.frames == 0 {
+                        return Err("frames must be above 0".to_string());
+                    }
+                }
+                _ => return Err(format!("unknown benchmark setting '{}'", name)),
+            }
+            Ok(())
+        });
+
+        Some(config)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct StageTimes {
+    min: f64,
+    max: f64,
+    total: f64,
+    count: u32,
+}
+
+impl Default for StageTimes {
+    fn default() -> StageTimes {
+        StageTimes { min: f64::MAX, max: 0.0, total: 0.0, count: 0 }
+    }
+}
+
+lazy_static! {
+    static ref STAGE_TIMES: Mutex<[StageTimes;4]> = Mutex::new(Default::default());
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Start recording stage times
+pub fn start() {
+    RUNNING.store(true, Ordering::Relaxed);
+}
+
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Record how long a stage took, given the audio timer reading from when it started. Does nothing outside of benchmark mode
+pub fn record(stage: Stage, start: f64) {
+    if !is_running() {
+        return;
+    }
+
+    let elapsed = audio::get_time() - start;
+    let times = &mut STAGE_TIMES.lock().unwrap()[stage as usize];
+    times.min = times.min.min(elapsed);
+    times.max = times.max.max(elapsed);
+    times.total += elapsed;
+    times.count += 1;
+}
+
+/// Log the min, max & average time of every stage, in milliseconds
+pub fn log_results() {
+    let stage_times = STAGE_TIMES.lock().unwrap();
+
+    db::log("Benchmark results (ms min / max / avg):");
+    for (name, times) in STAGE_NAMES.iter().zip(stage_times.iter()) {
+        if times.count == 0 {
+            db::log(format!("  {}: never ran", name).as_str());
+            continue;
+        }
+
+        let avg = times.total / times.count as f64;
+        db::log(format!("  {}: {:.3} / {:.3} / {:.3} ({} samples)", name, times.min * 1000.0, times.max * 1000.0, avg * 1000.0, times.count).as_str());
+    }
+}
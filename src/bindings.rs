@@ -0,0 +1,533 @@
+use std::collections::BTreeMap;
+
+use dbsdk_rs::{db, gamepad::{GamepadButton, GamepadState}};
+
+use input::{self, StickConfig};
+use options;
+
+pub const CONTROLS_PATH: &str = "/cd/content/controls.cfg";
+
+pub const KEY_ESCAPE: i32 = 27;
+pub const KEY_BACKSPACE: i32 = 127;
+pub const KEY_CTRL: i32 = 0x80 + 0x1d;
+pub const KEY_SHIFT: i32 = 0x80 + 0x36;
+pub const KEY_ALT: i32 = 0x80 + 0x38;
+pub const KEY_LEFT_ARROW: i32 = 0xac;
+pub const KEY_UP_ARROW: i32 = 0xad;
+pub const KEY_RIGHT_ARROW: i32 = 0xae;
+pub const KEY_DOWN_ARROW: i32 = 0xaf;
+pub const KEY_PAUSE: i32 = 0xff;
+
+// toggles whether the automap follows the player, which has to be off for it to pan
+pub const KEY_AUTOMAP_FOLLOW: i32 = b'f' as i32;
+
+// stand-ins for DOOM's previous & next weapon keys (q & e by default), which are looked up when pressed since .doomrc can rebind them
+pub const ACTION_PREV_WEAPON: i32 = -1;
+pub const ACTION_NEXT_WEAPON: i32 = -2;
+
+const DEFAULT_STICK_THRESHOLD: i16 = 1024;
+const DEFAULT_LEFT_STICK: StickConfig = StickConfig { deadzone: 1024, outer: i16::MAX };
+const DEFAULT_RIGHT_STICK: StickConfig = StickConfig { deadzone: 0, outer: i16::MAX };
+const DEFAULT_STICK_RUN_THRESHOLD: f32 = 0.8;
+
+// range of the right stick's response curve exponent
+const MIN_RESPONSE_CURVE: f32 = 0.25;
+const MAX_RESPONSE_CURVE: f32 = 4.0;
+
+/// A direction a stick can be pushed in
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StickDir {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl StickDir {
+    /// Whether the stick is pushed past the threshold in this direction. A threshold inside the deadzone is raised to the deadzone's edge
+    pub fn is_pushed(self, state: &GamepadState, threshold: i16, stick: &StickConfig) -> bool {
+        self.is_pushed_on(state.left_stick_x, state.left_stick_y, threshold, stick)
+    }
+
+    /// Same as is_pushed, for a stick with the given axes
+    pub fn is_pushed_on(self, x: i16, y: i16, threshold: i16, stick: &StickConfig) -> bool {
+        let threshold = threshold.max(stick.deadzone);
+        match self {
+            StickDir::Left => x < -threshold,
+            StickDir::Right => x > threshold,
+            StickDir::Up => y > threshold,
+            StickDir::Down => y < -threshold,
+        }
+    }
+}
+
+/// Which physical stick strafes, moves forwards & back, and turns. Everything past this (deadzones, curves, automap panning) goes by the
+/// role a stick was given rather than where it is on the controller, so `deadzone` always applies to the movement stick
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StickLayout {
+    /// Left stick moves & strafes, right stick turns (and looks)
+    Classic,
+    /// Left stick moves & turns, right stick strafes (and looks), like DOOM's console ports
+    TwinStick,
+    /// Classic with the sticks swapped
+    SouthPaw,
+}
+
+impl StickLayout {
+    /// Rearrange the state's stick axes so the left stick fields hold strafe & forward/back, and the right stick fields hold turn & look
+    pub fn apply(self, state: GamepadState) -> GamepadState {
+        match self {
+            StickLayout::Classic => state,
+            StickLayout::TwinStick => GamepadState {
+                left_stick_x: state.right_stick_x,
+                right_stick_x: state.left_stick_x,
+                ..state
+            },
+            StickLayout::SouthPaw => GamepadState {
+                left_stick_x: state.right_stick_x,
+                left_stick_y: state.right_stick_y,
+                right_stick_x: state.left_stick_x,
+                right_stick_y: state.left_stick_y,
+                ..state
+            },
+        }
+    }
+}
+
+/// What the bindings are being applied to this frame
+pub struct MapContext {
+    /// Run unless the run button is held (which walks instead)
+    pub always_run: bool,
+    /// The left stick moves through doom_set_move rather than pressing its direction keys
+    pub analog_move: bool,
+    /// The right stick presses the automap pan keys
+    pub automap_pan: bool,
+}
+
+/// Which DOOM keys each gamepad button & left stick direction presses, plus how the right stick is turned into mouse motion (or automap panning). <br/>
+/// GamepadButton doesn't implement Hash (dbsdk builds bitmask without its std feature), so the tables are BTreeMaps keyed on its derived Ord instead
+#[derive(Clone)]
+pub struct KeyBindings {
+    pub buttons: BTreeMap<GamepadButton, Vec<i32>>,
+    pub stick: BTreeMap<StickDir, Vec<i32>>,
+    /// Keys the right stick presses to pan the automap while it's open (in place of turning)
+    pub automap_pan: BTreeMap<StickDir, Vec<i32>>,
+    /// How far the left stick has to be pushed before its direction keys are pressed
+    pub stick_threshold: i16,
+    /// Left stick deadzone & outer edge, used by the direction keys, analog movement & the run threshold
+    pub left_stick: StickConfig,
+    /// Right stick deadzone & outer edge, used for turning (and mouse look)
+    pub right_stick: StickConfig,
+    /// Left stick magnitude (0 to 1, after the deadzone) past which run is held automatically. 1 never runs
+    pub stick_run_threshold: f32,
+    /// Multiplier for right stick turn speed
+    pub mouse_sensitivity: f32,
+    /// Exponent of the right stick's turn response curve (1 is linear)
+    pub response_curve: f32,
+    /// Apply response_curve to the right stick's vertical axis (mouse look) as well as turning
+    pub response_curve_look: bool,
+    /// Reverse the direction the right stick turns
+    pub invert_x: bool,
+    /// Feed the right stick's vertical axis to DOOM as vertical mouse motion
+    pub mouse_look_enabled: bool,
+    /// Which physical stick does what. The rest of the bindings refer to the sticks by role: "left stick" means the movement stick
+    pub stick_layout: StickLayout,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let buttons = BTreeMap::from([
+            (GamepadButton::R2, vec![KEY_CTRL]),
+            (GamepadButton::L2, vec![KEY_SHIFT]),
+            (GamepadButton::R1, vec![ACTION_NEXT_WEAPON]),
+            (GamepadButton::L1, vec![ACTION_PREV_WEAPON]),
+            (GamepadButton::Start, vec![KEY_PAUSE]),
+            (GamepadButton::A, vec![input::KEY_SPACE, input::KEY_ENTER]),
+            (GamepadButton::B, vec![KEY_BACKSPACE]),
+            (GamepadButton::X, vec![KEY_ESCAPE]),
+            (GamepadButton::Left, vec![b',' as i32]),
+            (GamepadButton::Right, vec![b'.' as i32]),
+            (GamepadButton::Up, vec![KEY_UP_ARROW]),
+            (GamepadButton::Down, vec![KEY_DOWN_ARROW]),
+        ]);
+
+        let stick = BTreeMap::from([
+            (StickDir::Left, vec![b',' as i32]),
+            (StickDir::Right, vec![b'.' as i32]),
+            (StickDir::Up, vec![KEY_UP_ARROW]),
+            (StickDir::Down, vec![KEY_DOWN_ARROW]),
+        ]);
+
+        // DOOM's automap pans with the arrow keys
+        let automap_pan = BTreeMap::from([
+            (StickDir::Left, vec![KEY_LEFT_ARROW]),
+            (StickDir::Right, vec![KEY_RIGHT_ARROW]),
+            (StickDir::Up, vec![KEY_UP_ARROW]),
+            (StickDir::Down, vec![KEY_DOWN_ARROW]),
+        ]);
+
+        KeyBindings {
+            buttons,
+            stick,
+            automap_pan,
+            stick_threshold: DEFAULT_STICK_THRESHOLD,
+            left_stick: DEFAULT_LEFT_STICK,
+            right_stick: DEFAULT_RIGHT_STICK,
+            stick_run_threshold: DEFAULT_STICK_RUN_THRESHOLD,
+            mouse_sensitivity: 1.0,
+            response_curve: 1.0,
+            response_curve_look: false,
+            invert_x: false,
+            mouse_look_enabled: false,
+            stick_layout: StickLayout::Classic,
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Work out every key the bindings want held for this controller state, sorted & without duplicates, along with the keys each held button resolved to. <br/>
+    /// Each button's keys are resolved (by `resolve`) when it's first pressed & carried over from `held_button_keys` (last frame's) until it's let go,
+    /// so the same keys get released even if what they'd resolve to has changed in between (like the menu opening or closing).
+    /// What they resolve to depends on DOOM's state, which is why it's passed in rather than looked up here. <br/>
+    /// Diffing the result against last frame's with input::key_events gives the presses & releases to send
+    pub fn map_input<F: Fn(&[i32]) -> Vec<i32>>(&self, state: &GamepadState, ctx: &MapContext, held_button_keys: &BTreeMap<GamepadButton, Vec<i32>>, resolve: F) -> (Vec<i32>, BTreeMap<GamepadButton, Vec<i32>>) {
+        let mut wanted: Vec<i32> = Vec::new();
+        let mut held = BTreeMap::new();
+
+        if ctx.automap_pan {
+            for (dir, keys) in &self.automap_pan {
+                if dir.is_pushed_on(state.right_stick_x, state.right_stick_y, self.stick_threshold, &self.right_stick) {
+                    wanted.extend(resolve(keys));
+                }
+            }
+        }
+
+        for (button, keys) in &self.buttons {
+            if state.is_pressed(*button) {
+                let keys = held_button_keys.get(button).cloned().unwrap_or_else(|| resolve(keys));
+                wanted.extend(keys.iter());
+                held.insert(*button, keys);
+            }
+        }
+
+        if ctx.always_run {
+            // with always run on, the run button works the other way round & walks instead
+            let walk = wanted.contains(&KEY_SHIFT);
+            wanted.retain(|v| *v != KEY_SHIFT);
+            if !walk {
+                wanted.push(KEY_SHIFT);
+            }
+        }
+        else if self.left_stick.magnitude(state.left_stick_x, state.left_stick_y) > self.stick_run_threshold {
+            // pushing the left stick most of the way holds run, same as a keyboard player would
+            wanted.push(KEY_SHIFT);
+        }
+
+        // with analog movement on, the left stick bypasses the movement keys entirely (the d-pad still uses them)
+        if !ctx.analog_move {
+            for (dir, keys) in &self.stick {
+                if dir.is_pushed(state, self.stick_threshold, &self.left_stick) {
+                    wanted.extend(resolve(keys));
+                }
+            }
+        }
+
+        wanted.sort_unstable();
+        wanted.dedup();
+        (wanted, held)
+    }
+
+    /// Whether the right stick is pushed in any of the automap pan directions
+    pub fn automap_pan_pushed(&self, state: &GamepadState) -> bool {
+        self.automap_pan.keys().any(|dir| dir.is_pushed_on(state.right_stick_x, state.right_stick_y, self.stick_threshold, &self.right_stick))
+    }
+
+    /// Load bindings from a file, starting from the default scheme. Only the buttons named in the file are rebound,
+    /// and lines which fail to parse are logged & leave that binding at its default
+    pub fn load_from_file(path: &str) -> KeyBindings {
+        let mut bindings = KeyBindings::default();
+
+        options::read_config(path, |name, value| {
+            let name = name.to_ascii_lowercase();
+
+            if name == "stick_threshold" {
+                let threshold: i16 = value.parse().map_err(|_| format!("expected a number, got '{}'", value))?;
+                if threshold <= 0 {
+                    return Err("stick_threshold must be above 0".to_string());
+                }
+                bindings.stick_threshold = threshold;
+            }
+            else if name == "deadzone" {
+                bindings.left_stick.deadzone = parse_stick_edge(value)?;
+            }
+            else if name == "outer" {
+                bindings.left_stick.outer = parse_stick_edge(value)?;
+            }
+            else if name == "right_deadzone" {
+                bindings.right_stick.deadzone = parse_stick_edge(value)?;
+            }
+            else if name == "right_outer" {
+                bindings.right_stick.outer = parse_stick_edge(value)?;
+            }
+            else if name == "stick_run_threshold" {
+                let threshold: f32 = value.parse().map_err(|_| format!("expected a number, got '{}'", value))?;
+                if !(0.0..=1.0).contains(&threshold) {
+                    return Err("stick_run_threshold must be between 0 and 1".to_string());
+                }
+                bindings.stick_run_threshold = threshold;
+            }
+            else if name == "mouse_sensitivity" {
+                let sensitivity: f32 = value.parse().map_err(|_| format!("expected a number, got '{}'", value))?;
+                if !(sensitivity > 0.0 && sensitivity <= 10.0) {
+                    return Err("mouse_sensitivity must be above 0, and at most 10".to_string());
+                }
+                bindings.mouse_sensitivity = sensitivity;
+            }
+            else if name == "response_curve" {
+                let exponent: f32 = value.parse().map_err(|_| format!("expected a number, got '{}'", value))?;
+                if !(MIN_RESPONSE_CURVE..=MAX_RESPONSE_CURVE).contains(&exponent) {
+                    return Err(format!("response_curve must be between {} and {}", MIN_RESPONSE_CURVE, MAX_RESPONSE_CURVE));
+                }
+                bindings.response_curve = exponent;
+            }
+            else if name == "response_curve_look" {
+                bindings.response_curve_look = options::parse_bool(value)?;
+            }
+            else if name == "invert_x" {
+                bindings.invert_x = options::parse_bool(value)?;
+            }
+            else if name == "mouse_look" {
+                bindings.mouse_look_enabled = options::parse_bool(value)?;
+            }
+            else if name == "stick_layout" {
+                bindings.stick_layout = match value.to_ascii_lowercase().as_str() {
+                    "classic" => StickLayout::Classic,
+                    "twinstick" => StickLayout::TwinStick,
+                    "southpaw" => StickLayout::SouthPaw,
+                    _ => return Err(format!("expected classic, twinstick or southpaw, got '{}'", value)),
+                };
+            }
+            else if let Some(dir) = name.strip_prefix("stick_") {
+                let dir = parse_stick_dir(dir)?;
+                bindings.stick.insert(dir, parse_keys(value)?);
+            }
+            else if let Some(dir) = name.strip_prefix("automap_") {
+                let dir = parse_stick_dir(dir)?;
+                bindings.automap_pan.insert(dir, parse_keys(value)?);
+            }
+            else {
+                let button = options::parse_button(&name)?;
+                bindings.buttons.insert(button, parse_keys(value)?);
+            }
+
+            Ok(())
+        });
+
+        // the deadzone & outer edge can be given in either order, so they can only be checked against each other once everything's loaded
+        if bindings.left_stick.deadzone >= bindings.left_stick.outer {
+            db::log(format!("{}: deadzone must be below outer, using the default left stick settings", path).as_str());
+            bindings.left_stick = DEFAULT_LEFT_STICK;
+        }
+        if bindings.right_stick.deadzone >= bindings.right_stick.outer {
+            db::log(format!("{}: right_deadzone must be below right_outer, using the default right stick settings", path).as_str());
+            bindings.right_stick = DEFAULT_RIGHT_STICK;
+        }
+
+        bindings
+    }
+}
+
+/// Parse a stick deadzone or outer edge, as a raw axis value
+fn parse_stick_edge(value: &str) -> Result<i16, String> {
+    let edge: i16 = value.parse().map_err(|_| format!("expected a number, got '{}'", value))?;
+    if edge < 0 {
+        return Err("must be between 0 and 32767".to_string());
+    }
+    Ok(edge)
+}
+
+fn parse_stick_dir(value: &str) -> Result<StickDir, String> {
+    match value {
+        "left" => Ok(StickDir::Left),
+        "right" => Ok(StickDir::Right),
+        "up" => Ok(StickDir::Up),
+        "down" => Ok(StickDir::Down),
+        _ => Err(format!("unknown stick direction '{}'", value)),
+    }
+}
+
+/// Parse a comma separated list of keys. "none" leaves the button unbound
+fn parse_keys(value: &str) -> Result<Vec<i32>, String> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(Vec::new());
+    }
+
+    value.split(',').map(|v| parse_key(v.trim())).collect()
+}
+
+/// Parse a single key, given either by name, as a single character, or as a DOOM keycode number (decimal or 0x hex)
+fn parse_key(value: &str) -> Result<i32, String> {
+    let lower = value.to_ascii_lowercase();
+
+    let named = match lower.as_str() {
+        "tab" => Some(input::KEY_TAB),
+        "enter" => Some(input::KEY_ENTER),
+        "escape" => Some(KEY_ESCAPE),
+        "space" => Some(input::KEY_SPACE),
+        "backspace" => Some(KEY_BACKSPACE),
+        "ctrl" => Some(KEY_CTRL),
+        "shift" => Some(KEY_SHIFT),
+        "alt" => Some(KEY_ALT),
+        "left" => Some(KEY_LEFT_ARROW),
+        "up" => Some(KEY_UP_ARROW),
+        "right" => Some(KEY_RIGHT_ARROW),
+        "down" => Some(KEY_DOWN_ARROW),
+        "pause" => Some(KEY_PAUSE),
+        "prevweapon" => Some(ACTION_PREV_WEAPON),
+        "nextweapon" => Some(ACTION_NEXT_WEAPON),
+        _ => None,
+    };
+
+    if let Some(key) = named {
+        return Ok(key);
+    }
+
+    // F1-F10 are contiguous, F11 & F12 aren't
+    if let Some(n) = lower.strip_prefix('f').and_then(|v| v.parse::<i32>().ok()) {
+        return match n {
+            1..=10 => Ok(0x80 + 0x3a + n),
+            11 => Ok(0x80 + 0x57),
+            12 => Ok(0x80 + 0x58),
+            _ => Err(format!("unknown key '{}'", value)),
+        };
+    }
+
+    let number = match lower.strip_prefix("0x") {
+        Some(hex) => i32::from_str_radix(hex, 16).ok(),
+        None if lower.len() > 1 => lower.parse::<i32>().ok(),
+        None => None,
+    };
+
+    match number {
+        Some(key) if (0..=255).contains(&key) => Ok(key),
+        Some(_) => Err(format!("keycode must be between 0 and 255, got '{}'", value)),
+        // DOOM's letter keys are all lowercase
+        None if lower.len() == 1 && lower.is_ascii() => Ok(lower.as_bytes()[0] as i32),
+        None => Err(format!("unknown key '{}'", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbsdk_rs::gamepad::GamepadButtonMask;
+    use input::KeyEvent;
+    use options::AButtonMode;
+
+    const IN_GAME: MapContext = MapContext { always_run: false, analog_move: false, automap_pan: false };
+
+    fn state(buttons: &[GamepadButton], left_stick: (i16, i16)) -> GamepadState {
+        let mut button_mask = GamepadButtonMask::none();
+        for button in buttons {
+            button_mask.set(*button);
+        }
+        GamepadState { button_mask, left_stick_x: left_stick.0, left_stick_y: left_stick.1, right_stick_x: 0, right_stick_y: 0 }
+    }
+
+    fn press(key: i32) -> KeyEvent {
+        KeyEvent { key, down: true }
+    }
+
+    fn release(key: i32) -> KeyEvent {
+        KeyEvent { key, down: false }
+    }
+
+    /// Runs frames through map_input & key_events the same way the app does, keeping what was held from one frame to the next
+    struct Harness {
+        bindings: KeyBindings,
+        wanted: Vec<i32>,
+        held_button_keys: BTreeMap<GamepadButton, Vec<i32>>,
+    }
+
+    impl Harness {
+        fn new(bindings: KeyBindings) -> Harness {
+            Harness { bindings, wanted: Vec::new(), held_button_keys: BTreeMap::new() }
+        }
+
+        fn frame<F: Fn(&[i32]) -> Vec<i32>>(&mut self, state: GamepadState, ctx: &MapContext, resolve: F) -> Vec<KeyEvent> {
+            let (wanted, held) = self.bindings.map_input(&state, ctx, &self.held_button_keys, resolve);
+            let events = input::key_events(&self.wanted, &wanted);
+            self.wanted = wanted;
+            self.held_button_keys = held;
+            events
+        }
+
+        fn step(&mut self, state: GamepadState) -> Vec<KeyEvent> {
+            self.frame(state, &IN_GAME, |keys| keys.to_vec())
+        }
+    }
+
+    #[test]
+    fn simultaneous_presses_all_go_down_together() {
+        let mut harness = Harness::new(KeyBindings::default());
+
+        let events = harness.step(state(&[GamepadButton::A, GamepadButton::R2], (0, 0)));
+        assert_eq!(events, vec![press(input::KEY_ENTER), press(input::KEY_SPACE), press(KEY_CTRL)]);
+
+        let events = harness.step(state(&[], (0, 0)));
+        assert_eq!(events, vec![release(input::KEY_ENTER), release(input::KEY_SPACE), release(KEY_CTRL)]);
+    }
+
+    #[test]
+    fn stick_presses_once_past_threshold() {
+        let mut harness = Harness::new(KeyBindings::default());
+
+        assert!(harness.step(state(&[], (DEFAULT_STICK_THRESHOLD, 0))).is_empty());
+        assert_eq!(harness.step(state(&[], (DEFAULT_STICK_THRESHOLD + 1, 0))), vec![press(b'.' as i32)]);
+        assert_eq!(harness.step(state(&[], (DEFAULT_STICK_THRESHOLD, 0))), vec![release(b'.' as i32)]);
+    }
+
+    #[test]
+    fn threshold_inside_deadzone_is_raised_to_its_edge() {
+        let defaults = KeyBindings::default();
+        let left_stick = StickConfig { deadzone: 2000, ..defaults.left_stick };
+        let mut harness = Harness::new(KeyBindings { stick_threshold: 100, left_stick, ..defaults });
+
+        assert!(harness.step(state(&[], (0, 1000))).is_empty());
+        assert!(harness.step(state(&[], (0, 2000))).is_empty());
+        assert_eq!(harness.step(state(&[], (0, 2001))), vec![press(KEY_UP_ARROW)]);
+    }
+
+    #[test]
+    fn button_held_across_a_menu_change_releases_what_it_pressed() {
+        let mut harness = Harness::new(KeyBindings::default());
+        let resolve = |menu_active: bool| move |keys: &[i32]| ::resolve_keys_for(keys, AButtonMode::Context, menu_active);
+        let a = state(&[GamepadButton::A], (0, 0));
+
+        // pressed in game, then the menu opens while it's still down - it keeps sending use until it's let go
+        assert_eq!(harness.frame(a, &IN_GAME, resolve(false)), vec![press(input::KEY_SPACE)]);
+        assert!(harness.frame(a, &IN_GAME, resolve(true)).is_empty());
+        assert_eq!(harness.frame(state(&[], (0, 0)), &IN_GAME, resolve(true)), vec![release(input::KEY_SPACE)]);
+
+        // & the next press picks select, which is still the one released once the menu closes
+        assert_eq!(harness.frame(a, &IN_GAME, resolve(true)), vec![press(input::KEY_ENTER)]);
+        assert!(harness.frame(a, &IN_GAME, resolve(false)).is_empty());
+        assert_eq!(harness.frame(state(&[], (0, 0)), &IN_GAME, resolve(false)), vec![release(input::KEY_ENTER)]);
+        assert!(harness.held_button_keys.is_empty());
+    }
+
+    #[test]
+    fn dpad_and_stick_share_a_key() {
+        let mut harness = Harness::new(KeyBindings::default());
+        let up = DEFAULT_STICK_THRESHOLD * 2;
+
+        // both asking for up arrow only presses it once
+        assert_eq!(harness.step(state(&[GamepadButton::Up], (0, up))), vec![press(KEY_UP_ARROW)]);
+        assert_eq!(harness.wanted, vec![KEY_UP_ARROW]);
+
+        // & it stays down until neither wants it
+        assert!(harness.step(state(&[], (0, up))).is_empty());
+        assert!(harness.step(state(&[GamepadButton::Up], (0, 0))).is_empty());
+        assert_eq!(harness.step(state(&[], (0, 0))), vec![release(KEY_UP_ARROW)]);
+    }
+}
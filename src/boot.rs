@@ -0,0 +1,210 @@
+use std::ffi::{CString, c_char};
+
+use dbsdk_rs::{db, io};
+
+use files;
+use options::{self, Options};
+
+pub const BOOT_CONFIG_PATH: &str = "/cd/content/boot.cfg";
+
+// raw DOOM command line, for anything the boot manifest doesn't cover
+pub const LAUNCH_CONFIG_PATH: &str = "/cd/content/launch.cfg";
+
+// where DOOM is told its WADs are (DOOMWADDIR) - doom_open also looks in the other content roots for anything that isn't there
+pub const CONTENT_DIR: &str = files::CONTENT_ROOTS[0];
+
+const DEFAULT_SOUNDFONT: &str = "soundfont.sf2";
+
+// extra soundfonts which can be switched between in-game, in any of the content roots
+const SOUNDFONT_DIR: &str = "soundfonts";
+
+// IWADs DOOM can identify, in the order it looks for them in the content directory
+const IWAD_NAMES: [&str;7] = ["doom2f.wad", "doom2.wad", "plutonia.wad", "tnt.wad", "doomu.wad", "doom.wad", "doom1.wad"];
+
+/// Command line arguments passed to doom_init
+pub struct DoomArgs {
+    args: Vec<CString>,
+}
+
+impl DoomArgs {
+    pub fn new() -> DoomArgs {
+        // DOOM skips argv[0] when looking for parameters, same as a real command line
+        DoomArgs { args: vec![CString::new("doom").unwrap()] }
+    }
+
+    pub fn arg(&mut self, arg: &str) -> &mut DoomArgs {
+        self.args.push(CString::new(arg).expect("Argument contains a null byte"));
+        self
+    }
+
+    /// Append whitespace separated arguments from a launch file. Lines starting with # are ignored, and a missing or empty file adds nothing
+    pub fn args_from_file(&mut self, path: &str) -> &mut DoomArgs {
+        let text = match options::read_text_file(path) {
+            Some(v) => v,
+            None => return self,
+        };
+
+        for line in text.lines().map(|v| v.trim()).filter(|v| !v.starts_with('#')) {
+            for arg in line.split_whitespace() {
+                // a file read off disc could contain anything, so bad arguments are skipped rather than panicking
+                match CString::new(arg) {
+                    Ok(v) => self.args.push(v),
+                    Err(_) => db::log(format!("{}: skipping argument containing a null byte", path).as_str()),
+                }
+            }
+        }
+
+        self
+    }
+
+    /// Value following the first occurrence of a parameter, matching how DOOM reads its command line
+    pub fn value_of(&self, param: &str) -> Option<&str> {
+        let idx = self.args.iter().skip(1).position(|v| v.to_string_lossy().eq_ignore_ascii_case(param))? + 1;
+        self.args.get(idx + 1).and_then(|v| v.to_str().ok())
+    }
+
+    /// Log the full command line
+    pub fn log(&self) {
+        let args: Vec<String> = self.args.iter().skip(1).map(|v| v.to_string_lossy().into_owned()).collect();
+        db::log(format!("DOOM args: {}", if args.is_empty() { "(none)".to_string() } else { args.join(" ") }).as_str());
+    }
+
+    /// Build the argv array. DOOM holds on to argv for as long as it runs, so the arguments are leaked here
+    pub fn into_argv(self) -> (i32, *const *const c_char) {
+        let argv: Vec<*const c_char> = self.args.into_iter().map(|v| v.into_raw() as *const c_char).collect();
+        let argc = argv.len() as i32;
+        (argc, Box::leak(argv.into_boxed_slice()).as_ptr())
+    }
+}
+
+/// Boot manifest, which picks the content DOOM starts up with. Anything left unspecified falls back to auto-discovery & defaults. <br/>
+/// Lines which aren't boot settings are applied as regular options, so display defaults etc can live in the same file
+#[derive(Default)]
+pub struct BootConfig {
+    /// IWAD file name (auto-detected by DOOM if not given)
+    pub iwad: Option<String>,
+    /// PWADs, in load order
+    pub pwads: Vec<String>,
+    /// Soundfont used for music
+    pub soundfont: Option<String>,
+    /// Starting skill, 1 to 5
+    pub skill: Option<i32>,
+    /// Starting map, either [map] or [episode, map]
+    pub warp: Vec<i32>,
+}
+
+impl BootConfig {
+    /// Load the boot manifest, applying any option lines in it to the given options
+    pub fn load(path: &str, options: &mut Options) -> BootConfig {
+        let mut config = BootConfig::default();
+
+        options::read_config(path, |name, value| {
+            match name {
+                "iwad" => config.iwad = Some(value.to_string()),
+                "pwad" => config.pwads.push(value.to_string()),
+                "soundfont" => config.soundfont = Some(value.to_string()),
+                "skill" => {
+                    let skill: i32 = value.parse().map_err(|_| format!("expected a number, got '{}'", value))?;
+                    if !(1..=5).contains(&skill) {
+                        return Err("skill must be between 1 and 5".to_string());
+                    }
+                    config.skill = Some(skill);
+                }
+                "warp" => {
+                    let warp: Result<Vec<i32>, _> = value.split_whitespace().map(|v| v.parse::<i32>()).collect();
+                    config.warp = match warp {
+                        Ok(v) if (1..=2).contains(&v.len()) => v,
+                        _ => return Err(format!("warp must be a map number, or an episode & map number, got '{}'", value)),
+                    };
+                }
+                _ => options.set(name, value)?,
+            }
+
+            Ok(())
+        });
+
+        config
+    }
+
+    pub fn soundfont_path(&self) -> String {
+        content_path(self.soundfont.as_deref().unwrap_or(DEFAULT_SOUNDFONT))
+    }
+
+    /// Every soundfont which can be switched between - the .sf2 files in the soundfonts directories, sorted by name - along with the index of the one to start with. <br/>
+    /// That's the boot manifest's soundfont (or the default one, if it's there), which is added to the list if it isn't already in it
+    pub fn soundfont_paths(&self) -> (Vec<String>, usize) {
+        let mut paths: Vec<String> = files::list_content(SOUNDFONT_DIR).into_iter()
+            .filter(|(name, _)| name.to_ascii_lowercase().ends_with(".sf2"))
+            .map(|(_, path)| path)
+            .collect();
+
+        let start = self.soundfont_path();
+        let idx = match paths.iter().position(|v| *v == start) {
+            Some(idx) => idx,
+            None if self.soundfont.is_some() || paths.is_empty() || io::file_exists(&start) => {
+                paths.insert(0, start);
+                0
+            }
+            None => 0,
+        };
+
+        (paths, idx)
+    }
+
+    pub fn doom_args(&self) -> DoomArgs {
+        let mut args = DoomArgs::new();
+
+        if let Some(iwad) = &self.iwad {
+            args.arg("-iwad").arg(iwad);
+        }
+
+        if !self.pwads.is_empty() {
+            args.arg("-file");
+            for pwad in &self.pwads {
+                args.arg(&content_path(pwad));
+            }
+        }
+
+        if let Some(skill) = self.skill {
+            args.arg("-skill").arg(&skill.to_string());
+        }
+
+        if !self.warp.is_empty() {
+            args.arg("-warp");
+            for v in &self.warp {
+                args.arg(&v.to_string());
+            }
+        }
+
+        args
+    }
+
+    pub fn log(&self) {
+        let warp: Vec<String> = self.warp.iter().map(|v| v.to_string()).collect();
+
+        db::log(format!("Boot config: iwad={} pwads=[{}] soundfont={} skill={} warp={}",
+            self.iwad.as_deref().unwrap_or("(auto)"),
+            self.pwads.join(", "),
+            self.soundfont_path(),
+            self.skill.map(|v| v.to_string()).unwrap_or_else(|| "(default)".to_string()),
+            if warp.is_empty() { "(none)".to_string() } else { warp.join(" ") }).as_str());
+    }
+}
+
+/// Find the IWAD DOOM is going to load, the same way DOOM searches for it. If a specific IWAD was requested (with -iwad), only that one is looked for
+pub fn find_iwad(requested: Option<&str>) -> Option<String> {
+    IWAD_NAMES.iter()
+        .filter(|name| requested.is_none_or(|v| v.eq_ignore_ascii_case(name)))
+        .map(|name| content_path(name))
+        .find(|path| io::file_exists(path))
+}
+
+/// Resolve a path from a config file - absolute paths are used as-is, anything else is relative to the content roots
+fn content_path(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    }
+    else {
+        files::find_content(path)
+    }
+}
@@ -0,0 +1,148 @@
+use std::{collections::BTreeMap, ffi::{c_char, CStr, CString}, io::{self, Read, Seek, Write}, sync::Mutex};
+
+use dbsdk_rs::io::{self as db_io, DirectoryInfo, FileMode, FileStream, IOError};
+use lazy_static::lazy_static;
+
+// directories content (WADs, music & soundfonts) is looked up in, in order - the disc, then the memory card for anything the disc doesn't have,
+// so mods & extra music can be added without touching the original media
+pub const CONTENT_ROOTS: [&str;2] = ["/cd/content", "/ma"];
+
+// mirrors dbsdk's internal directory entry layout
+#[repr(C)]
+struct NativeDirectoryInfo {
+    name: [c_char;32],
+    created: u64,
+    modified: u64,
+    size: i32,
+    is_directory: u32,
+}
+
+extern "C" {
+    fn fs_openDir(pathstr: *const c_char) -> i32;
+    fn fs_readDir(dir: i32) -> *const NativeDirectoryInfo;
+    fn fs_closeDir(dir: i32);
+}
+
+lazy_static! {
+    // every handle DOOM currently has open (through doom_open) & the path it was opened with, so leaked handles can be spotted
+    static ref OPEN_FILES: Mutex<BTreeMap<i32, String>> = Mutex::new(BTreeMap::new());
+}
+
+/// Record a handle opened through doom_open
+pub fn track_open(handle: i32, path: &str) {
+    OPEN_FILES.lock().unwrap().insert(handle, path.to_string());
+}
+
+/// Forget a handle closed through doom_close
+pub fn track_close(handle: i32) {
+    OPEN_FILES.lock().unwrap().remove(&handle);
+}
+
+/// Every handle DOOM currently has open, & the path each was opened with
+pub fn open_files() -> Vec<(i32, String)> {
+    OPEN_FILES.lock().unwrap().iter().map(|(k, v)| (*k, v.clone())).collect()
+}
+
+/// Read an entire file into memory
+pub fn read_file_fully(path: &str) -> Result<Vec<u8>, io::Error> {
+    let mut file = FileStream::open(path, FileMode::Read).map_err(|e| match e {
+        IOError::FileNotFound => io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path)),
+        e => io::Error::other(format!("couldn't open {}: {:?}", path, e)),
+    })?;
+
+    file.seek(io::SeekFrom::End(0))?;
+    let size = file.position();
+    file.seek(io::SeekFrom::Start(0))?;
+    let mut buf: Vec<u8> = vec![0;size as usize];
+    file.read_exact(&mut buf)?;
+
+    Ok(buf)
+}
+
+/// Check whether files can be written in a directory, by writing (and leaving behind) an empty marker file there
+pub fn probe_writable(dir: &str) -> bool {
+    let path = format!("{}/.dbdoom", dir);
+
+    match FileStream::open(&path, FileMode::Write) {
+        Ok(mut file) => file.write_all(&[]).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Names of the files (not subdirectories) in a directory, or nothing if it doesn't exist. <br/>
+/// dbsdk's DirectoryInfo can only read a single entry (read() consumes it), so it's just used to check the directory opens before listing it here
+pub fn list_files(dir: &str) -> Vec<String> {
+    if DirectoryInfo::open(dir).is_err() {
+        return Vec::new();
+    }
+
+    let mut names = Vec::new();
+    let path = CString::new(dir).expect("Path contains a null byte");
+
+    unsafe {
+        let handle = fs_openDir(path.as_ptr());
+
+        loop {
+            let entry = fs_readDir(handle);
+            if entry.is_null() {
+                break;
+            }
+
+            if (*entry).is_directory == 0 {
+                names.push(CStr::from_ptr((*entry).name.as_ptr()).to_string_lossy().into_owned());
+            }
+        }
+
+        fs_closeDir(handle);
+    }
+
+    names
+}
+
+/// Find a content file from its path relative to the content roots, returning its path in the first root which has it. <br/>
+/// If none of them do it's the path in the first root, so that's what shows up in any errors
+pub fn find_content(relative: &str) -> String {
+    CONTENT_ROOTS.iter()
+        .map(|root| format!("{}/{}", root, relative))
+        .find(|path| db_io::file_exists(path))
+        .unwrap_or_else(|| format!("{}/{}", CONTENT_ROOTS[0], relative))
+}
+
+/// Names of the files in a content directory across all the content roots, sorted by name, with each name's path in the first root which has it
+pub fn list_content(relative_dir: &str) -> Vec<(String, String)> {
+    let mut found: Vec<(String, String)> = Vec::new();
+
+    for root in CONTENT_ROOTS {
+        let dir = format!("{}/{}", root, relative_dir);
+        for name in list_files(&dir) {
+            if !found.iter().any(|(v, _)| *v == name) {
+                let path = format!("{}/{}", dir, name);
+                found.push((name, path));
+            }
+        }
+    }
+
+    found.sort();
+    found
+}
+
+/// How a file is opened through doom_open, from a C fopen-style mode string
+#[derive(Clone, Copy, PartialEq)]
+pub enum OpenMode {
+    Read,
+    Write,
+    Append,
+}
+
+impl OpenMode {
+    /// Parse a mode string like "rb" or "a". The "+" (update) modes aren't supported, since dbsdk file handles are either read or write only
+    pub fn parse(mode: &str) -> Option<OpenMode> {
+        // binary & text mode are the same thing here
+        match mode.replace('b', "").as_str() {
+            "r" => Some(OpenMode::Read),
+            "w" => Some(OpenMode::Write),
+            "a" => Some(OpenMode::Append),
+            _ => None,
+        }
+    }
+}
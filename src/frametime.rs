@@ -0,0 +1,42 @@
+// how quickly the smoothed frame time follows changes, per frame (lower is steadier)
+const SMOOTHING: f32 = 0.05;
+
+// measured frame times are capped at this, so a long hitch (like a slow disc read) doesn't throw everything timed by it far ahead
+const MAX_DELTA: f32 = 0.25;
+
+/// Measures the real time between frames, & keeps a smoothed average for the FPS counter
+#[derive(Default)]
+pub struct FrameTimer {
+    last: Option<f64>,
+    smoothed: f32,
+}
+
+impl FrameTimer {
+    pub fn new() -> FrameTimer {
+        FrameTimer::default()
+    }
+
+    /// Record the start of a frame at the given time (in seconds), returning how long the previous frame took. <br/>
+    /// Passing None (no real clock available) resets the timer, and there's no measurement until the clock comes back
+    pub fn tick(&mut self, now: Option<f64>) -> Option<f32> {
+        let delta = match (self.last, now) {
+            (Some(last), Some(now)) => Some(((now - last) as f32).clamp(0.0, MAX_DELTA)),
+            _ => None,
+        };
+        self.last = now;
+
+        match delta {
+            Some(delta) if self.smoothed > 0.0 => self.smoothed += (delta - self.smoothed) * SMOOTHING,
+            Some(delta) => self.smoothed = delta,
+            None if now.is_none() => self.smoothed = 0.0,
+            None => {}
+        }
+
+        delta
+    }
+
+    /// Smoothed time per frame in seconds, if there's been a measurement yet
+    pub fn frame_time(&self) -> Option<f32> {
+        if self.smoothed > 0.0 { Some(self.smoothed) } else { None }
+    }
+}
@@ -0,0 +1,22 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// bytes currently allocated through doom_malloc (including each block's size preamble), & the most there's ever been at once
+static CURRENT_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn record_alloc(bytes: usize) {
+    let current = CURRENT_BYTES.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+}
+
+pub fn record_free(bytes: usize) {
+    CURRENT_BYTES.fetch_sub(bytes, Ordering::Relaxed);
+}
+
+pub fn current_bytes() -> usize {
+    CURRENT_BYTES.load(Ordering::Relaxed)
+}
+
+pub fn peak_bytes() -> usize {
+    PEAK_BYTES.load(Ordering::Relaxed)
+}
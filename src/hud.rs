@@ -0,0 +1,63 @@
+use dbsdk_rs::vdp::Color32;
+
+use overlay::{self, Overlay};
+
+// how long the HUD stays up after the last change, the last part of which is spent fading out
+const SHOW_TIME: f32 = 2.0;
+const FADE_TIME: f32 = 0.5;
+
+const TEXT_SCALE: f32 = 2.0;
+const BAR_WIDTH: f32 = 256.0;
+const BAR_HEIGHT: f32 = 12.0;
+const PADDING: f32 = 12.0;
+
+// distance from the bottom of the screen, leaving DOOM's status bar visible
+const BOTTOM_MARGIN: f32 = 96.0;
+
+/// Feedback for the settings which can be adjusted in-game: the last changed setting's name & value over a bar showing where it sits in its range. <br/>
+/// Each change replaces whatever was showing, & it fades out a couple of seconds after the last one
+#[derive(Default)]
+pub struct SettingsHud {
+    label: String,
+    timer: f32,
+    // how full the bar is, from 0 to 1
+    fill: f32,
+}
+
+impl SettingsHud {
+    pub fn new() -> SettingsHud {
+        SettingsHud::default()
+    }
+
+    /// Show a setting's new value, with `fill` (0 to 1) giving where it sits between its lowest & highest values
+    pub fn show(&mut self, label: &str, fill: f32) {
+        self.label = label.to_string();
+        self.fill = fill.clamp(0.0, 1.0);
+        self.timer = SHOW_TIME;
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        self.timer = (self.timer - delta).max(0.0);
+    }
+
+    pub fn draw(&self, overlay: &mut Overlay) {
+        if self.timer <= 0.0 {
+            return;
+        }
+
+        let alpha = (self.timer / FADE_TIME).min(1.0);
+        let text_height = overlay::LINE_HEIGHT * TEXT_SCALE;
+        let width = BAR_WIDTH.max(Overlay::text_width(&self.label, TEXT_SCALE)) + PADDING * 2.0;
+        let height = text_height + BAR_HEIGHT + PADDING * 3.0;
+        let x = ((overlay::SCREEN_WIDTH - width) * 0.5).floor();
+        let y = overlay::SCREEN_HEIGHT - BOTTOM_MARGIN - height;
+
+        overlay.draw_rect(x, y, width, height, Color32::new(0, 0, 0, (192.0 * alpha) as u8));
+        overlay.draw_text_centered(y + PADDING, TEXT_SCALE, &self.label, Color32::new(255, 255, 255, (255.0 * alpha) as u8));
+
+        let bar_x = ((overlay::SCREEN_WIDTH - BAR_WIDTH) * 0.5).floor();
+        let bar_y = y + PADDING * 2.0 + text_height;
+        overlay.draw_rect(bar_x, bar_y, BAR_WIDTH, BAR_HEIGHT, Color32::new(64, 64, 64, (255.0 * alpha) as u8));
+        overlay.draw_rect(bar_x, bar_y, (BAR_WIDTH * self.fill).floor(), BAR_HEIGHT, Color32::new(255, 0, 0, (255.0 * alpha) as u8));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, VecDeque};
+
+// DOOM keycodes (see doom_key_t in PureDOOM.h)
+pub const KEY_TAB: i32 = 9;
+pub const KEY_ENTER: i32 = 13;
+pub const KEY_SPACE: i32 = 32;
+pub const KEY_F6: i32 = 0x80 + 0x40;
+pub const KEY_F9: i32 = 0x80 + 0x43;
+pub const KEY_Y: i32 = b'y' as i32;
+
+// DOOM's movement speeds (forwardmove & sidemove in PureDOOM.h), indexed by [walk, run]
+const FORWARD_MOVE: [f32;2] = [25.0, 50.0];
+const SIDE_MOVE: [f32;2] = [24.0, 40.0];
+const MAX_MOVE: i32 = 50;
+
+// most mouse motion sent to DOOM in one call. DOOM turns by mousex * 8 (scaled up to 1.4x by its mouse sensitivity) in a 16-bit angleturn,
+// so this stays well clear of overflowing it. At most MAX_MOUSE_CARRY more is held over for the following frames
+const MAX_MOUSE_UNITS: f32 = 1024.0;
+const MAX_MOUSE_CARRY: f32 = MAX_MOUSE_UNITS * 4.0;
+
+/// Rotate a stick reading clockwise by the given number of quarter turns
+pub fn rotate_stick(x: i16, y: i16, quarter_turns: usize) -> (i16, i16) {
+    match quarter_turns % 4 {
+        1 => (y, x.saturating_neg()),
+        2 => (x.saturating_neg(), y.saturating_neg()),
+        3 => (y.saturating_neg(), x),
+        _ => (x, y),
+    }
+}
+
+/// Convert a raw stick axis reading to the -1..1 range <br/>
+/// i16::MIN would otherwise land just past -1, and anything that isn't a finite number is treated as centered
+pub fn normalize_axis(value: i16) -> f32 {
+    let v = (value as f32 / 32767.0).clamp(-1.0, 1.0);
+    if v.is_finite() { v } else { 0.0 }
+}
+
+/// Add motion to a mouse accumulator & take out whatever whole units it now holds, up to MAX_MOUSE_UNITS <br/>
+/// The fractional part is carried between frames, along with anything over the cap (so one long frame turns over the next few instead of snapping all at once).
+/// The carry is bounded too, so it can't grow without bound & lose precision (or end up non-finite) over a long session
+pub fn take_mouse_units(acc: &mut f32, motion: f32) -> i32 {
+    *acc += motion;
+    if !acc.is_finite() {
+        *acc = 0.0;
+    }
+
+    let units = acc.trunc().clamp(-MAX_MOUSE_UNITS, MAX_MOUSE_UNITS);
+    *acc = (*acc - units).clamp(-MAX_MOUSE_CARRY, MAX_MOUSE_CARRY);
+    units as i32
+}
+
+/// How a stick's raw readings are mapped to deflection. Both values are raw axis readings (0 to 32767)
+#[derive(Clone, Copy)]
+pub struct StickConfig {
+    /// Deflection treated as centered, so a drifting stick reads as 0
+    pub deadzone: i16,
+    /// Deflection treated as pushed all the way, for sticks which don't quite reach the edge
+    pub outer: i16,
+}
+
+impl StickConfig {
+    /// Rescale a normalized deflection so it's 0 at the deadzone's edge & 1 at the outer edge
+    fn rescale(&self, value: f32) -> f32 {
+        let deadzone = normalize_axis(self.deadzone);
+        let outer = normalize_axis(self.outer);
+
+        let magnitude = value.abs();
+        if magnitude <= deadzone {
+            return 0.0;
+        }
+
+        value.signum() * ((magnitude - deadzone) / (outer - deadzone)).min(1.0)
+    }
+
+    /// Map a single raw axis reading to -1..1
+    pub fn axis(&self, value: i16) -> f32 {
+        self.rescale(normalize_axis(value))
+    }
+
+    /// How far the stick is pushed in any direction, from 0 to 1
+    pub fn magnitude(&self, x: i16, y: i16) -> f32 {
+        self.rescale(normalize_axis(x).hypot(normalize_axis(y)).min(1.0))
+    }
+}
+
+/// Apply a power response curve to a stick axis (-1 to 1). Exponents above 1 give finer control near the center, below 1 a quicker response, & 1 leaves it linear
+pub fn response_curve(value: f32, exponent: f32) -> f32 {
+    value.signum() * value.abs().powf(exponent)
+}
+
+/// Convert a left stick reading into DOOM (forward, side) movement units
+pub fn analog_move(x: i16, y: i16, run: bool, stick: &StickConfig) -> (i32, i32) {
+    let speed = run as usize;
+    let forward = stick.axis(y) * FORWARD_MOVE[speed];
+    let side = stick.axis(x) * SIDE_MOVE[speed];
+
+    ((forward.round() as i32).clamp(-MAX_MOVE, MAX_MOVE), (side.round() as i32).clamp(-MAX_MOVE, MAX_MOVE))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: i32,
+    pub down: bool,
+}
+
+/// Presses & releases needed to go from the keys held now to the keys wanted
+pub fn key_events(held: &[i32], wanted: &[i32]) -> Vec<KeyEvent> {
+    let presses = wanted.iter().filter(|v| !held.contains(v)).map(|v| KeyEvent { key: *v, down: true });
+    let releases = held.iter().filter(|v| !wanted.contains(v)).map(|v| KeyEvent { key: *v, down: false });
+    presses.chain(releases).collect()
+}
+
+struct QueuedKey {
+    event: KeyEvent,
+    delay: u32,
+}
+
+/// Queue of synthesized key events which are delivered to DOOM right before doom_update. <br/>
+/// Each event waits for its delay (in frames) after the event in front of it has been delivered, so sequences play out in order
+#[derive(Default)]
+pub struct KeyQueue {
+    pending: VecDeque<QueuedKey>,
+}
+
+impl KeyQueue {
+    pub fn new() -> KeyQueue {
+        KeyQueue::default()
+    }
+
+    pub fn push(&mut self, event: KeyEvent, delay: u32) {
+        self.pending.push_back(QueuedKey { event, delay });
+    }
+
+    /// Queue a press of the given key after `delay` frames, followed by its release on the next frame
+    pub fn push_tap(&mut self, key: i32, delay: u32) {
+        self.push(KeyEvent { key, down: true }, delay);
+        self.push(KeyEvent { key, down: false }, 1);
+    }
+
+    /// Whether every queued event has been delivered
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Deliver every event which is due this frame
+    pub fn dispatch<F: FnMut(KeyEvent)>(&mut self, mut send: F) {
+        while let Some(front) = self.pending.front_mut() {
+            if front.delay > 0 {
+                front.delay -= 1;
+                break;
+            }
+
+            let queued = self.pending.pop_front().unwrap();
+            send(queued.event);
+        }
+    }
+}
+
+/// Auto-repeat for held keys, since DOOM's menus only move once per key press
+#[derive(Default)]
+pub struct KeyRepeat {
+    // time left until each held key next repeats
+    timers: BTreeMap<i32, f32>,
+}
+
+impl KeyRepeat {
+    pub fn new() -> KeyRepeat {
+        KeyRepeat::default()
+    }
+
+    /// Advance the timers for the keys currently held, returning the ones which should be pressed again this frame. <br/>
+    /// A newly held key first repeats after `delay` seconds, then every `rate` seconds. Keys no longer held are forgotten
+    pub fn update(&mut self, held: &[i32], delta: f32, delay: f32, rate: f32) -> Vec<i32> {
+        self.timers.retain(|key, _| held.contains(key));
+
+        let mut due = Vec::new();
+        for key in held {
+            let timer = self.timers.entry(*key).or_insert(delay);
+            *timer -= delta;
+
+            if *timer <= 0.0 {
+                // only one repeat per frame, even if the frame was long enough for several
+                *timer = (*timer + rate).max(0.0);
+                due.push(*key);
+            }
+        }
+
+        due
+    }
+
+    pub fn clear(&mut self) {
+        self.timers.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(key: i32) -> KeyEvent {
+        KeyEvent { key, down: true }
+    }
+
+    fn release(key: i32) -> KeyEvent {
+        KeyEvent { key, down: false }
+    }
+
+    fn assert_in_range(v: f32) {
+        assert!(!v.is_nan() && (-1.0..=1.0).contains(&v), "{} is out of range", v);
+    }
+
+    #[test]
+    fn extreme_axis_readings_stay_in_range() {
+        assert_eq!(normalize_axis(i16::MAX), 1.0);
+        assert_eq!(normalize_axis(i16::MIN), -1.0);
+        assert_eq!(normalize_axis(0), 0.0);
+
+        let stick = StickConfig { deadzone: 1024, outer: 30000 };
+        assert_eq!(stick.axis(i16::MAX), 1.0);
+        assert_eq!(stick.axis(i16::MIN), -1.0);
+        assert_eq!(stick.magnitude(i16::MIN, i16::MIN), 1.0);
+        for value in [f32::MAX, f32::MIN, f32::INFINITY, f32::NEG_INFINITY, -1.0, 1.0] {
+            assert_in_range(stick.rescale(value));
+        }
+    }
+
+    #[test]
+    fn deadzone_just_inside_outer_edge_stays_in_range() {
+        let stick = StickConfig { deadzone: 29999, outer: 30000 };
+        assert_eq!(stick.axis(29999), 0.0);
+        assert_eq!(stick.axis(30000), 1.0);
+        assert_eq!(stick.axis(-30000), -1.0);
+
+        for value in [i16::MIN, i16::MIN + 1, -30001, -29999, 0, 29999, 30001, i16::MAX] {
+            assert_in_range(stick.axis(value));
+            assert_in_range(stick.magnitude(value, value));
+        }
+    }
+
+    #[test]
+    fn extreme_mouse_motion_stays_bounded() {
+        for motion in [f32::MAX, f32::MIN, f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let mut acc = 0.0;
+            let units = take_mouse_units(&mut acc, motion);
+            assert!(units.abs() <= MAX_MOUSE_UNITS as i32);
+            assert!(acc.is_finite() && acc.abs() <= MAX_MOUSE_CARRY);
+        }
+    }
+
+    #[test]
+    fn mouse_motion_under_a_unit_builds_up() {
+        let mut acc = 0.0;
+        let units: Vec<i32> = (0..4).map(|_| take_mouse_units(&mut acc, 0.25)).collect();
+        assert_eq!(units, vec![0, 0, 0, 1]);
+        assert_eq!(acc, 0.0);
+
+        let units: Vec<i32> = (0..3).map(|_| take_mouse_units(&mut acc, -0.4)).collect();
+        assert_eq!(units, vec![0, 0, -1]);
+    }
+
+    #[test]
+    fn mouse_motion_over_the_cap_turns_over_the_next_frames() {
+        let mut acc = 0.0;
+        let units: Vec<i32> = (0..4).map(|i| take_mouse_units(&mut acc, if i == 0 { 3000.0 } else { 0.0 })).collect();
+        assert_eq!(units, vec![1024, 1024, 952, 0]);
+        assert_eq!(acc, 0.0);
+    }
+
+    #[test]
+    fn mouse_carry_is_capped() {
+        let mut acc = 0.0;
+        assert_eq!(take_mouse_units(&mut acc, -100000.0), -MAX_MOUSE_UNITS as i32);
+        assert_eq!(acc, -MAX_MOUSE_CARRY);
+
+        let drained: i32 = (0..8).map(|_| take_mouse_units(&mut acc, 0.0)).sum();
+        assert_eq!(drained, -MAX_MOUSE_CARRY as i32);
+        assert_eq!(acc, 0.0);
+    }
+
+    #[test]
+    fn non_finite_mouse_motion_resets_the_accumulator() {
+        for motion in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let mut acc = 0.5;
+            assert_eq!(take_mouse_units(&mut acc, motion), 0);
+            assert_eq!(acc, 0.0);
+            assert_eq!(take_mouse_units(&mut acc, 1.5), 1);
+        }
+    }
+
+    #[test]
+    fn key_events_presses_new_keys_then_releases_old_ones() {
+        assert_eq!(key_events(&[1, 2], &[2, 3]), vec![press(3), release(1)]);
+        assert!(key_events(&[1, 2], &[1, 2]).is_empty());
+        assert_eq!(key_events(&[], &[4, 5]), vec![press(4), press(5)]);
+        assert_eq!(key_events(&[4, 5], &[]), vec![release(4), release(5)]);
+    }
+
+    /// Every event the queue delivers over the given number of frames, along with the frame it went out on
+    fn run_queue(queue: &mut KeyQueue, frames: u32) -> Vec<(u32, KeyEvent)> {
+        let mut sent = Vec::new();
+        for frame in 0..frames {
+            queue.dispatch(|event| sent.push((frame, event)));
+        }
+        sent
+    }
+
+    #[test]
+    fn push_tap_releases_on_the_next_frame() {
+        let mut queue = KeyQueue::new();
+        queue.push_tap(KEY_F6, 0);
+
+        assert_eq!(run_queue(&mut queue, 4), vec![(0, press(KEY_F6)), (1, release(KEY_F6))]);
+    }
+
+    #[test]
+    fn push_tap_delays_count_from_the_previous_event() {
+        let mut queue = KeyQueue::new();
+        queue.push_tap(KEY_F6, 0);
+        queue.push_tap(KEY_Y, 2);
+
+        let sent = run_queue(&mut queue, 8);
+        assert_eq!(sent, vec![(0, press(KEY_F6)), (1, release(KEY_F6)), (3, press(KEY_Y)), (4, release(KEY_Y))]);
+    }
+}
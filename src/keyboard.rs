@@ -0,0 +1,142 @@
+use dbsdk_rs::vdp::Color32;
+
+use bindings;
+use input;
+use overlay::{self, Overlay};
+
+// every row is laid out on the same grid of columns, so moving up & down stays lined up
+const COLUMNS: usize = 10;
+
+// rows of single character keys (shown uppercase, but DOOM's letter keys are all lowercase)
+const CHAR_ROWS: [&str;4] = ["1234567890", "QWERTYUIOP", "ASDFGHJKL-", "ZXCVBNM,./"];
+
+// bottom row of wider keys, as (label, key, columns spanned)
+const SPECIAL_ROW: [(&str, i32, usize);3] = [
+    ("SPACE", input::KEY_SPACE, 4),
+    ("BKSP", bindings::KEY_BACKSPACE, 3),
+    ("ENTER", input::KEY_ENTER, 3),
+];
+
+const ROWS: usize = CHAR_ROWS.len() + 1;
+
+const KEY_WIDTH: f32 = 40.0;
+const KEY_HEIGHT: f32 = 32.0;
+const KEY_GAP: f32 = 4.0;
+const PADDING: f32 = 12.0;
+const TEXT_SCALE: f32 = 2.0;
+
+/// One key on the keyboard, covering `span` columns starting from `start`
+struct Key {
+    label: &'static str,
+    key: i32,
+    start: usize,
+    span: usize,
+}
+
+/// The key covering the given row & column
+fn key_at(row: usize, col: usize) -> Key {
+    if let Some(chars) = CHAR_ROWS.get(row) {
+        return Key {
+            label: &chars[col..col + 1],
+            key: chars.as_bytes()[col].to_ascii_lowercase() as i32,
+            start: col,
+            span: 1,
+        };
+    }
+
+    let mut start = 0;
+    for (label, key, span) in SPECIAL_ROW {
+        if col < start + span {
+            return Key { label, key, start, span };
+        }
+        start += span;
+    }
+
+    unreachable!("column {} is past the end of the keyboard", col)
+}
+
+/// On-screen keyboard for typing into DOOM with a controller (cheat codes, savegame names etc), navigated with the d-pad
+#[derive(Default)]
+pub struct VirtualKeyboard {
+    open: bool,
+    row: usize,
+    col: usize,
+}
+
+impl VirtualKeyboard {
+    pub fn new() -> VirtualKeyboard {
+        VirtualKeyboard::default()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Show or hide the keyboard. The cursor stays where it was left for next time
+    pub fn set_open(&mut self, open: bool) {
+        self.open = open;
+    }
+
+    /// Move the cursor one key over, wrapping around at the edges
+    pub fn move_cursor(&mut self, dx: i32, dy: i32) {
+        if dy != 0 {
+            self.row = (self.row as i32 + dy).rem_euclid(ROWS as i32) as usize;
+        }
+
+        // wider keys are stepped over in one go, & the cursor always lands on a key's first column
+        let current = key_at(self.row, self.col);
+        if dx > 0 {
+            self.col = (current.start + current.span) % COLUMNS;
+        }
+        else if dx < 0 {
+            self.col = key_at(self.row, (current.start + COLUMNS - 1) % COLUMNS).start;
+        }
+        else {
+            self.col = current.start;
+        }
+    }
+
+    /// DOOM keycode of the key under the cursor
+    pub fn selected_key(&self) -> i32 {
+        key_at(self.row, self.col).key
+    }
+
+    pub fn draw(&self, overlay: &mut Overlay) {
+        if !self.open {
+            return;
+        }
+
+        let grid_width = COLUMNS as f32 * KEY_WIDTH + (COLUMNS - 1) as f32 * KEY_GAP;
+        let grid_height = ROWS as f32 * KEY_HEIGHT + (ROWS - 1) as f32 * KEY_GAP;
+        let hint_height = overlay::LINE_HEIGHT * TEXT_SCALE;
+
+        // kept to the bottom of the screen, so DOOM's messages (like cheat confirmations) stay visible at the top
+        let x = ((overlay::SCREEN_WIDTH - grid_width) * 0.5).floor();
+        let y = overlay::SCREEN_HEIGHT - grid_height - hint_height - PADDING * 3.0;
+
+        overlay.draw_rect(x - PADDING, y - PADDING, grid_width + PADDING * 2.0, grid_height + hint_height + PADDING * 3.0, Color32::new(0, 0, 0, 192));
+
+        let selected = key_at(self.row, self.col);
+
+        for row in 0..ROWS {
+            let mut col = 0;
+            while col < COLUMNS {
+                let key = key_at(row, col);
+                let key_x = x + key.start as f32 * (KEY_WIDTH + KEY_GAP);
+                let key_y = y + row as f32 * (KEY_HEIGHT + KEY_GAP);
+                let key_width = key.span as f32 * KEY_WIDTH + (key.span - 1) as f32 * KEY_GAP;
+
+                let color = if row == self.row && key.start == selected.start { Color32::new(255, 0, 0, 255) } else { Color32::new(64, 64, 64, 255) };
+                overlay.draw_rect(key_x, key_y, key_width, KEY_HEIGHT, color);
+
+                let text_x = (key_x + (key_width - Overlay::text_width(key.label, TEXT_SCALE)) * 0.5).floor();
+                let text_y = (key_y + (KEY_HEIGHT - overlay::LINE_HEIGHT * TEXT_SCALE) * 0.5).floor();
+                overlay.draw_text(text_x, text_y, TEXT_SCALE, key.label, Color32::new(255, 255, 255, 255));
+
+                col = key.start + key.span;
+            }
+        }
+
+        overlay.draw_text_centered(y + grid_height + PADDING, TEXT_SCALE, "A: TYPE  B: CLOSE", Color32::new(255, 255, 0, 255));
+    }
+}
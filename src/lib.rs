@@ -1,87 +1,727 @@
+// host test builds have no main to reach everything from, so most of the crate looks unused to them
+#![cfg_attr(test, allow(dead_code, unused_imports))]
+
 extern crate lazy_static;
 extern crate dbsdk_rs;
 
+mod benchmark;
+mod bindings;
+mod boot;
+mod files;
+mod frametime;
+mod heap;
+mod hud;
+mod input;
+mod keyboard;
+mod log;
+mod memfs;
+mod music;
+mod options;
+mod overlay;
+mod replay;
+mod resample;
+mod rumble;
+mod screenshot;
+mod toast;
+mod wav;
+
+#[cfg(test)]
+mod test_alloc;
+
 use lazy_static::lazy_static;
-use std::{ffi::{c_void, c_char, CStr}, ptr::{self, slice_from_raw_parts}, convert::TryFrom, alloc::Layout, sync::RwLock, io::{Read, Seek}};
+use std::{collections::{BTreeMap, BTreeSet, VecDeque}, ffi::{c_void, c_char, CStr, CString}, ptr::{self, slice_from_raw_parts}, convert::TryFrom, alloc::Layout, sync::{Mutex, RwLock, atomic::{AtomicBool, AtomicI32, Ordering}}};
+
+use dbsdk_rs::{vdp::{self, Color32, Rectangle, PackedVertex, Texture}, db, io::{self, FileMode}, math::{Vector4, Vector2}, gamepad::{Gamepad, GamepadSlot, GamepadState, GamepadButtonMask, GamepadButton}, audio::{AudioSample, self}};
+
+use benchmark::{BenchmarkConfig, Stage};
+use bindings::{KeyBindings, MapContext, StickDir};
+use boot::{BootConfig, DoomArgs};
+use files::OpenMode;
+use frametime::FrameTimer;
+use hud::SettingsHud;
+use input::{KeyEvent, KeyQueue, KeyRepeat};
+use keyboard::VirtualKeyboard;
+use music::{Ducker, MidiCache, MidiPreload};
+use options::{AButtonMode, CanvasFormat, Options, TurnMode};
+use overlay::Overlay;
+use replay::InputReplay;
+use resample::ResampleMode;
+use rumble::Rumble;
+use toast::ToastQueue;
+
+// most buffers of audio processed in one frame when catching up after a slow frame, so a long stall can't turn into a runaway loop
+const AUDIO_MAX_BUFFERS_PER_FRAME: usize = 4;
+
+// fewest previous audio buffers kept alive, however short the lookahead
+const AUDIO_MIN_BUFFERS: usize = 3;
+
+// step size when adjusting audio latency compensation in-game
+const LATENCY_COMP_STEP: f64 = 0.005;
+
+// how long the screen flashes when the latency calibration test sound is fired
+const CALIBRATION_FLASH_FRAMES: u32 = 4;
+
+// consecutive audio sample allocation failures before falling back to a single mono voice
+const AUDIO_MONO_FALLBACK_FAILURES: u32 = 3;
+
+// consecutive successful buffers in forced mono before trying stereo again (about 30 seconds)
+const AUDIO_STEREO_RETRY_BUFFERS: u32 = 640;
+
+// keys which auto-repeat while held in DOOM's menus
+const MENU_REPEAT_KEYS: [i32;4] = [bindings::KEY_UP_ARROW, bindings::KEY_DOWN_ARROW, bindings::KEY_LEFT_ARROW, bindings::KEY_RIGHT_ARROW];
+
+// doom_get_demo_playback value while a demo given with -playdemo is playing
+const DEMO_PLAYBACK_SINGLE: i32 = 2;
+
+// doom_get_automap_mode values
+const AUTOMAP_CLOSED: i32 = 0;
+const AUTOMAP_FOLLOWING: i32 = 1;
+
+// sfxenum_t values (see PureDOOM.h)
+const SFX_PISTOL: i32 = 1;
+
+// frames in a row the audio timer can go without advancing before DOOM's clock falls back to counting frames (about half a second)
+const CLOCK_STALL_FRAMES: u32 = 30;
+
+// game speeds the speed combo steps through, starting back at normal speed after the last one
+const TIME_SCALES: [f32;5] = [1.0, 2.0, 4.0, 0.25, 0.5];
+
+// how long a content loading problem stays on screen at boot
+const LOAD_ERROR_SECONDS: f32 = 8.0;
+
+// how long the goodbye message stays up after quitting, before the app halts (about a second)
+const SHUTDOWN_FRAMES: u32 = 60;
+
+// reverb unit settings, with reverb_amount as the wet level
+const REVERB_ROOM_SIZE: f32 = 0.6;
+const REVERB_DAMPING: f32 = 0.5;
+const REVERB_WIDTH: f32 = 1.0;
+
+// samples the first buffer after an underrun takes to fade in from where the last good buffer left off
+const AUDIO_RESYNC_CROSSFADE: usize = 64;
+
+// samples at the end of each submitted chunk which lead into the next chunk's first sample (see splice_seam).
+// 1 only appends that sample; more also blend the chunk's tail towards it, for a smoother seam if the rate or buffer size changes between chunks
+const AUDIO_SEAM_OVERLAP: usize = 1;
+
+// consecutive near-silent buffers (like while paused) before they stop being submitted, & the loudest sample which still counts as silent
+const AUDIO_SILENCE_BUFFERS: u32 = 8;
+const AUDIO_SILENCE_LEVEL: u16 = 16;
+
+// how often the underrun count gets logged, in seconds
+const AUDIO_UNDERRUN_LOG_INTERVAL: f64 = 60.0;
+
+// frames auto_advance_screens holds use down for, long enough that a tic runs in between at DOOM's 35Hz
+const AUTO_ADVANCE_HOLD_FRAMES: u32 = 4;
+
+// mouse units per second the right stick sends at full deflection (scaled by the mouse_sensitivity control setting)
+const MOUSE_SPEED: f32 = 4096.0;
+
+// aspect ratio of the physical display we're presenting on
+const DISPLAY_ASPECT: f32 = 4.0 / 3.0;
+
+// DOOM's framebuffer is always requested as RGBA, & read back at the same size
+const DOOM_FB_CHANNELS: i32 = 4;
+
+// HOME as far as DOOM is concerned, where savegames & .doomrc live
+const SAVE_DIR: &str = "/ma";
+const SAVE_DIR_CSTR: &[u8] = b"/ma\0";
+
+/// Resolve a file name against the save directory, leaving full paths as they are
+fn save_path(name: &str) -> String {
+    if name.starts_with('/') { name.to_string() } else { format!("{}/{}", SAVE_DIR, name) }
+}
+
+/// Resolve a demo's bare file name: one recorded to the memory card is played from there, & otherwise it's looked up in the content roots
+fn demo_path(name: &str) -> String {
+    let path = save_path(name);
+    if io::file_exists(&path) { path } else { files::find_content(name) }
+}
+
+/// Blend the start of a chunk in from the given sample, so it doesn't jump straight to a new level
+fn crossfade_from(chunk: &mut [i16], from: i16, len: usize) {
+    let len = len.min(chunk.len());
+
+    for (i, v) in chunk.iter_mut().take(len).enumerate() {
+        let w = (i + 1) as i32;
+        *v = ((from as i32 * (len as i32 - w) + *v as i32 * w) / len as i32) as i16;
+    }
+}
+
+/// End a chunk with the first sample of the chunk that plays after it, so Dreambox's 2-tap sampling has the right sample to interpolate towards at the end. <br/>
+/// With an overlap above 1, the chunk's last `overlap - 1` samples are also blended progressively towards that sample, so the two meet without a step
+fn splice_seam(chunk: &mut Vec<i16>, next_first: i16, overlap: usize) {
+    let blend = overlap.saturating_sub(1).min(chunk.len());
+    let n = (blend + 1) as i32;
+    let start = chunk.len() - blend;
+
+    for (i, v) in chunk[start..].iter_mut().enumerate() {
+        let w = (i + 1) as i32;
+        *v = ((*v as i32 * (n - w) + next_first as i32 * w) / n) as i16;
+    }
+    chunk.push(next_first);
+}
+
+/// Push a channel's newest chunk onto its queue & take out the one due to be submitted, if the queue is now deeper than `depth`. <br/>
+/// It comes out faded in from `crossfade` (if that's set) & spliced onto the chunk behind it, with `last_sample` updated to its new last sample.
+/// Chunks left over from a lowered depth are dropped as stale, & the one returned fades in from the last good sample instead
+fn next_audio_chunk(queue: &mut VecDeque<Vec<i16>>, chunk: Vec<i16>, depth: usize, crossfade: &mut Option<i16>, last_sample: &mut i16) -> Option<Vec<i16>> {
+    queue.push_back(chunk);
+
+    // only one chunk can be scheduled at a time, so anything ahead of it is too late to play
+    let mut submit = None;
+    while queue.len() > depth {
+        if submit.is_some() {
+            *crossfade = Some(*last_sample);
+        }
+        submit = queue.pop_front();
+    }
+
+    let mut oldest = submit?;
+    if let Some(from) = crossfade.take() {
+        crossfade_from(&mut oldest, from, AUDIO_RESYNC_CROSSFADE);
+    }
+    splice_seam(&mut oldest, queue.front().unwrap()[0], AUDIO_SEAM_OVERLAP);
+    *last_sample = oldest[oldest.len() - 1];
+    Some(oldest)
+}
+
+const GAMEPAD_SLOTS: [GamepadSlot;4] = [GamepadSlot::SlotA, GamepadSlot::SlotB, GamepadSlot::SlotC, GamepadSlot::SlotD];
+const GAMEPAD_SLOT_NAMES: [&str;4] = ["A", "B", "C", "D"];
+
+/// Lookup table mapping each color channel value through a gamma curve
+fn gamma_lut(gamma: f32) -> [u8;256] {
+    let mut lut = [0;256];
+    for (i, v) in lut.iter_mut().enumerate() {
+        *v = ((i as f32 / 255.0).powf(1.0 / gamma) * 255.0).round() as u8;
+    }
+    lut
+}
+
+/// Pack an 8-bit color into RGB565, dropping the low bits of each channel
+fn pack_rgb565(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+/// The canvas UV shown at each corner of the screen quad, listed clockwise starting from the top left, for an image rotated clockwise by
+/// `turns` quarter turns & mirrored as given. `max_uv` is how much of the canvas the image covers
+fn quad_uvs(turns: usize, flip_h: bool, flip_v: bool, max_uv: (f32, f32)) -> [(f32, f32);4] {
+    // the UVs of DOOM's image are listed in the same order as the corners, so rotating it is just a matter of shifting which UV lands on which corner
+    let (max_u, max_v) = max_uv;
+    let uvs = [(0.0, 0.0), (max_u, 0.0), (max_u, max_v), (0.0, max_v)].map(|(u, v)| {
+        // mirroring happens in image space, before the rotation is applied
+        (if flip_h { max_u - u } else { u }, if flip_v { max_v - v } else { v })
+    });
+
+    [0, 1, 2, 3].map(|corner| uvs[(corner + 4 - turns % 4) % 4])
+}
+
+/// The pair of textures DOOM's frames are uploaded to, alternating between them
+fn create_canvas(size: (i32, i32), format: CanvasFormat) -> Vec<Texture> {
+    vec![
+        Texture::new(size.0, size.1, false, format.texture_format()).unwrap(),
+        Texture::new(size.0, size.1, false, format.texture_format()).unwrap(),
+    ]
+}
+
+fn neutral_gp_state() -> GamepadState {
+    GamepadState { button_mask: GamepadButtonMask::none(), left_stick_x: 0, left_stick_y: 0, right_stick_x: 0, right_stick_y: 0 }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum GameState {
+    Level,
+    Intermission,
+    Finale,
+    DemoScreen,
+}
+
+impl GameState {
+    fn query() -> GameState {
+        match unsafe { doom_get_gamestate() } {
+            0 => GameState::Level,
+            1 => GameState::Intermission,
+            2 => GameState::Finale,
+            _ => GameState::DemoScreen,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            GameState::Level => "level",
+            GameState::Intermission => "intermission",
+            GameState::Finale => "finale",
+            GameState::DemoScreen => "demo screen",
+        }
+    }
+}
+
+/// Actions which wait on the player to confirm them with A (or cancel with B)
+enum ConfirmAction {
+    Quicksave { slot: i32 },
+    Quickload,
+}
+
+/// Optional gate at boot which holds off all input until every button has been released once & then something is pressed
+#[derive(Clone, Copy, PartialEq)]
+enum StartGate {
+    WaitRelease,
+    WaitPress,
+    Open,
+}
+
+struct Confirmation {
+    prompt: String,
+    action: ConfirmAction,
+}
+
+/// The player's quicksave slot, put aside while an autosave points DOOM's quicksave at the autosave slot
+#[derive(Clone, Copy)]
+struct BorrowedQuicksave {
+    // the player's slot (negative if they hadn't picked one yet)
+    slot: i32,
+    // doom_get_input_tic once the autosave's keys had all been sent
+    sent_tic: Option<i32>,
+}
+
+/// Returns the player's quicksave slot once it's safe to put back: DOOM only reads the slot when the autosave's Y reaches its prompt,
+/// so that's after every queued key has been sent & DOOM has run a tic since (reading whatever was still waiting)
+fn quicksave_restore_due(borrowed: &mut Option<BorrowedQuicksave>, keys_pending: bool, input_tic: i32) -> Option<i32> {
+    let b = borrowed.as_mut()?;
+    if keys_pending {
+        b.sent_tic = None;
+        return None;
+    }
+
+    match b.sent_tic {
+        Some(tic) if input_tic > tic => borrowed.take().map(|b| b.slot),
+        Some(_) => None,
+        None => {
+            b.sent_tic = Some(input_tic);
+            None
+        }
+    }
+}
+
+/// What changed after recording an audio allocation
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum AllocChange {
+    None,
+    /// The first failure of a run
+    Failing,
+    /// Allocations are working again after a run of this many failures
+    Recovered(u32),
+    /// Too many failures in a row, so audio drops to a single mono voice
+    FallBackToMono,
+    /// A sustained run without failures in forced mono, so audio goes back to stereo
+    BackToStereo,
+}
+
+/// Keeps count of audio sample allocation failures & successes, deciding when to fall back to mono & when to try stereo again
+struct AllocTracker {
+    forced_mono: bool,
+    failures: u32,
+    successes: u32,
+}
+
+impl AllocTracker {
+    fn new() -> AllocTracker {
+        AllocTracker { forced_mono: false, failures: 0, successes: 0 }
+    }
 
-use dbsdk_rs::{vdp::{self, Color32, TextureFormat, Rectangle, PackedVertex, Texture}, db, io::{self, FileMode, FileStream}, math::{Vector4, Vector2}, gamepad::{Gamepad, self, GamepadSlot, GamepadState, GamepadButtonMask, GamepadButton}, audio::{AudioSample, self}};
+    /// Record whether the latest buffer's allocations all succeeded. `mono_fallback` is whether falling back to mono is allowed
+    fn record(&mut self, alloc_ok: bool, mono_fallback: bool) -> AllocChange {
+        if alloc_ok {
+            let failures = self.failures;
+            self.failures = 0;
+            self.successes += 1;
+
+            if self.forced_mono && self.successes >= AUDIO_STEREO_RETRY_BUFFERS {
+                self.forced_mono = false;
+                self.successes = 0;
+                return AllocChange::BackToStereo;
+            }
+            return if failures > 1 { AllocChange::Recovered(failures) } else { AllocChange::None };
+        }
 
-const AUDIO_LOOKAHEAD_TIME: f64 = 0.05;
+        self.successes = 0;
+        self.failures += 1;
 
-// technically sounds will be buffered up to (AUDIO_LOOKAHEAD_TIME * 2) seconds in advance
-// at a lookahead of 0.05s, w/ a buffer size of 512 samples @ 11025 Hz,
-// this is enough time to contain just over 2 buffers worth of audio (0.05 / (512.0/11025.0)) * 2 = 2.1533203125
-// so we round up and keep refs to the previous 3 buffers of audio to prevent them from being deallocated before they play
-const AUDIO_NUM_BUFFERS: usize = 3;
+        if mono_fallback && !self.forced_mono && self.failures >= AUDIO_MONO_FALLBACK_FAILURES {
+            self.forced_mono = true;
+            self.failures = 0;
+            return AllocChange::FallBackToMono;
+        }
+        if self.failures == 1 { AllocChange::Failing } else { AllocChange::None }
+    }
+}
 
 struct MyApp {
     time: f32,
+    // how fast DOOM's clock runs compared to real time (a debugging aid: above 1 fast-forwards, below 1 slows down)
+    time_scale: f32,
+    clock_last: f64,
+    clock_stalled_frames: u32,
     mx: f32,
-    prev_left: bool,
-    prev_right: bool,
-    prev_up: bool,
-    prev_down: bool,
-    canvas_tex: Texture,
-    prev_gp_state: GamepadState,
-    audio_buf: [[Option<AudioSample>;AUDIO_NUM_BUFFERS];2],
-    audio_queue: [Option<Vec<i16>>;2],
+    my: f32,
+    // DOOM's framebuffer is uploaded into whichever canvas isn't on screen, then the two swap, so a texture is never drawn from mid-upload.
+    // there are always two of them, until shutdown releases them
+    canvas_tex: Vec<Texture>,
+    front_buf: usize,
+    // DOOM's framebuffer size, & the power-of-two canvas size it's uploaded into the top left corner of
+    screen_size: (i32, i32),
+    canvas_size: (i32, i32),
+    gamma_lut: [u8;256],
+    // post-processed frames (like the gamma curve) are written here before uploading, while unprocessed ones upload straight from DOOM's framebuffer.
+    // it's allocated once at doom_fb_size & never resized after that, so post-processing never allocates
+    fb_scratch: Vec<u8>,
+    // counts frames up to render_every_n, DOOM's screen is only read, uploaded & drawn on the frames this wraps back to 0
+    render_frame: u32,
+    // previous state of every controller slot
+    prev_raw_gp_state: [GamepadState;4],
+    active_slot: usize,
+    // cleared while no controller is plugged in at all, which pauses the game & audio until one comes back
+    controller_connected: bool,
+    // seconds since the last input, & whether that's gone past idle_timeout (which pauses the game & audio like a disconnected controller)
+    idle_timer: f32,
+    idle: bool,
+    // seconds spent on the current intermission or finale screen since it was last advanced, for auto_advance_screens
+    auto_advance_timer: f32,
+    // debug readout of DOOM's heap usage
+    show_heap_stats: bool,
+    show_open_files: bool,
+    keyboard: VirtualKeyboard,
+    frame_timer: FrameTimer,
+    // set once follow mode has been toggled off to pan the automap, so it isn't toggled again while waiting for that to take effect
+    automap_follow_sent: bool,
+    rumble: Rumble,
+    // whether the active controller's motor is currently on, so it's only told when that changes
+    rumble_on: bool,
+    input_mask: GamepadButtonMask,
+    select_combo_used: bool,
+    // previous buffers of audio on each voice, kept alive until they've played (see audio_buffer_count)
+    audio_buf: [Vec<Option<AudioSample>>;2],
+    audio_queue: [VecDeque<Vec<i16>>;2],
+    // staging buffers for each channel's next chunk & for upsampling, reused between calls so process_audio doesn't allocate every time
+    audio_scratch: [Vec<i16>;2],
+    audio_upsample_scratch: Vec<i16>,
     audio_schedule_time: f64,
+    // last sample submitted on each voice, & the sample the next submitted chunk should fade in from after an underrun
+    audio_last_sample: [i16;2],
+    audio_crossfade: [Option<i16>;2],
+    // last output of each channel's low-pass filter, carried over between buffers
+    filter_state: [f32;2],
+    // near-silent buffers in a row, see AUDIO_SILENCE_BUFFERS
+    audio_silence_run: u32,
+    audio_underruns: u32,
+    audio_underrun_window: f64,
     next_buf: usize,
+    options: Options,
+    key_queue: KeyQueue,
+    game_state: GameState,
+    autosave_pending: bool,
+    // the player's quicksave slot while an autosave is using quicksave, put back once it's done
+    borrowed_quicksave: Option<BorrowedQuicksave>,
+    overlay: Overlay,
+    confirm: Option<Confirmation>,
+    midi_preload: Option<MidiPreload>,
+    start_gate: StartGate,
+    latency_calibration: bool,
+    calibration_flash: u32,
+    music_ducker: Ducker,
+    audio_alloc: AllocTracker,
+    toasts: ToastQueue,
+    // every soundfont which can be switched between, & the one in use
+    soundfonts: Vec<String>,
+    soundfont_idx: usize,
+    settings_hud: SettingsHud,
+    bindings: KeyBindings,
+    // keys pressed by each held button, remembered on press so the same ones get released even if the menu opened or closed in between
+    held_button_keys: BTreeMap<GamepadButton, Vec<i32>>,
+    keys_down: Vec<i32>,
+    // every key DOOM has been sent a press for & no release since, from any source (the controller, queued taps, inject_key, etc), for release_all_keys
+    held_keys: BTreeSet<i32>,
+    // doom_get_input_tic when each key in keys_down was pressed
+    key_press_tics: BTreeMap<i32, i32>,
+    // hold timers for the menu navigation keys
+    menu_repeat: KeyRepeat,
+    // doom_get_message_count as of the last message logged
+    hud_message_count: i32,
+    // doom_get_level_count as of the last time rumble was calibrated
+    level_count: i32,
+    // reverb_amount as last sent to the reverb unit (none until the first frame)
+    applied_reverb: Option<f32>,
+    // set while a -playdemo demo plays, which holds off controller input to DOOM
+    playing_demo: bool,
+    // input recording or playback, set up from -recordinput or -playinput in launch.cfg
+    input_replay: InputReplay,
+    shutdown_frames: Option<u32>,
+    // set once shutdown has released everything
+    released: bool,
+    // problem loading content at boot, which is shown on screen for a while
+    load_error: Option<String>,
+    load_error_time: f32,
+    // problem which kept DOOM from starting at all, shown on screen in place of the game
+    fatal_error: Option<String>,
 }
 
 impl MyApp {
     pub fn new() -> MyApp {
-        unsafe {
-            doom_set_print(doom_print);
-            doom_set_malloc(doom_malloc, doom_free);
-            doom_set_file_io(doom_open, doom_close, doom_read, doom_write, doom_seek, doom_tell, doom_eof);
-            doom_set_gettime(doom_gettime);
-            doom_set_exit(doom_exit);
-            doom_set_getenv(doom_getenv);
-            doom_set_playmus(doom_playmus);
-    
-            let args = [
-            ];
-    
-            doom_init(args.len() as i32, args.as_ptr(), 0);
+        // the boot manifest's option lines act as defaults, which the options file & saved settings then override
+        let mut options = Options::default();
+        let boot_config = BootConfig::load(boot::BOOT_CONFIG_PATH, &mut options);
+        options.load_files();
+        boot_config.log();
+
+        log::set_log_level(options.log_level);
+
+        // dbsdk has no mkdir, so saves go straight into the root of the memory card. if it can't be written to,
+        // saves are kept in memory instead so saving still works for the rest of the session
+        let save_dir_writable = files::probe_writable(SAVE_DIR);
+        if save_dir_writable {
+            db::log(format!("{} is writable, saving to memory card", SAVE_DIR).as_str());
+        }
+        else {
+            db::log(format!("{} isn't writable, saves will only be kept until the game is turned off", SAVE_DIR).as_str());
+            memfs::activate();
+        }
+
+        // launch.cfg args go after the boot manifest's. DOOM only looks at the first occurrence of a parameter, so the manifest wins if both set the same one
+        let mut doom_args = boot_config.doom_args();
+        doom_args.args_from_file(boot::LAUNCH_CONFIG_PATH);
+        if let Some(name) = &options.playdemo {
+            MyApp::add_playdemo_arg(&mut doom_args, name);
+        }
+        doom_args.log();
+
+        // the host's own launch arguments, which DOOM ignores. input recordings go on the memory card unless given a full path
+        let input_replay = match (doom_args.value_of("-recordinput"), doom_args.value_of("-playinput")) {
+            (Some(path), _) => InputReplay::record(&save_path(path)),
+            (None, Some(path)) => InputReplay::play(&save_path(path)),
+            (None, None) => InputReplay::Off,
+        };
+
+        // DOOM reads the time during init, so the clock has to be running before then
+        // benchmarks & input replays are timed by frame count, so every run plays out the same no matter how fast the frames go
+        let clock_start = audio::get_time();
+        if benchmark::is_running() {
+            db::log("Benchmark mode, timing DOOM by frame count");
+        }
+        else if input_replay.is_active() {
+            db::log("Recording or playing back input, timing DOOM by frame count");
+        }
+        else if clock_start.is_finite() {
+            unsafe {
+                CLOCK_START = clock_start;
+            }
+            USE_REAL_CLOCK.store(true, Ordering::Relaxed);
+        }
+        else {
+            db::log("Audio timer unavailable, timing DOOM by frame count");
+        }
+
+        // -skill & -warp start a new game straight away, unless a savegame is being loaded, which DOOM gives priority
+        if doom_args.value_of("-loadgame").is_some() && (doom_args.value_of("-skill").is_some() || doom_args.value_of("-warp").is_some()) {
+            db::log("Loading a savegame (-loadgame), so the new game from -skill/-warp won't be started");
+        }
+
+        // without an IWAD, DOOM would fail somewhere deep in init (or crash later on), so it isn't started at all
+        let fatal_error = match boot::find_iwad(doom_args.value_of("-iwad")) {
+            Some(iwad) => {
+                db::log(format!("Found IWAD: {}", iwad).as_str());
+                None
+            }
+            None => {
+                let error = match doom_args.value_of("-iwad") {
+                    Some(requested) => format!("{} NOT FOUND IN {}", requested, boot::CONTENT_DIR),
+                    None => format!("NO WAD FOUND IN {}", boot::CONTENT_DIR),
+                };
+                db::log(format!("Can't start DOOM: {}", error).as_str());
+                Some(error)
+            }
+        };
+
+        if fatal_error.is_none() {
+            unsafe {
+                doom_set_print(doom_print);
+                doom_set_malloc(doom_malloc, doom_free);
+                doom_set_file_io(doom_open, doom_close, doom_read, doom_write, doom_seek, doom_tell, doom_eof);
+                doom_set_gettime(doom_gettime);
+                doom_set_exit(doom_exit);
+                doom_set_getenv(doom_getenv);
+                doom_set_playmus(doom_playmus);
+
+                db::log(format!("DOOM init flags: {}", options::init_flag_names(options.init_flags)).as_str());
+
+                let (argc, argv) = doom_args.into_argv();
+                doom_init(argc, argv, options.init_flags);
+
+                if options.disable_attract {
+                    doom_set_attract_mode(0);
+                }
+
+                if options.disable_wipes {
+                    doom_set_wipes(0);
+                }
+            }
         }
 
-        // read & upload soundfont
-        {
-            let mut sf = FileStream::open("/cd/content/soundfont.sf2", FileMode::Read).unwrap();
-            sf.seek(std::io::SeekFrom::End(0)).unwrap();
-            let size = sf.position();
-            sf.seek(std::io::SeekFrom::Start(0)).unwrap();
-            let mut sf_buf: Vec<u8> = vec![0;size as usize];
-            sf.read_exact(&mut sf_buf).unwrap();
+        // read & upload soundfont. without a usable one the game still runs, just without any MIDI music
+        let (soundfonts, soundfont_idx) = boot_config.soundfont_paths();
+        db::log(format!("Soundfonts: {}", soundfonts.join(", ")).as_str());
+
+        let load_error = match load_soundfont(&soundfonts[soundfont_idx]) {
+            Ok(()) => {
+                db::log("Synth initialized");
+                None
+            }
+            Err((e, summary)) => {
+                db::log(format!("Failed initializing synth, music is disabled: {}", e).as_str());
+                music::set_music_available(false);
+                Some(format!("{} - MUSIC DISABLED", summary))
+            }
+        };
+
+        // DOOM won't start any music until its first update, so preloading can run over the first few frames before that
+        // DOOM's sound buffer holds both channels interleaved, so each voice gets half of it per buffer
+        let samples_per_buffer = usize::try_from(unsafe { doom_get_sound_buffer_size() }).unwrap() / 2;
 
-            audio::init_synth(&sf_buf).unwrap();
+        // everything on this side (scheduling, resampling & the low-pass filter) assumes DOOM's 11025 Hz mix. a higher rate would need DOOM to resample
+        // its sound effects as it mixes them, which it doesn't - the closest thing to higher quality output is upsampling it afterwards (audio_resample)
+        let doom_samplerate = unsafe { doom_get_sound_samplerate() };
+        if doom_samplerate != resample::DOOM_SAMPLERATE {
+            db::log(format!("Warning: DOOM mixes audio at {} Hz, but it's scheduled as {} Hz, so sound effects will play at the wrong pitch", doom_samplerate, resample::DOOM_SAMPLERATE).as_str());
+        }
+        let audio_buf_count = audio_buffer_count(options.audio_lookahead, samples_per_buffer);
+        db::log(format!("Audio lookahead {}s, keeping {} buffers per voice", options.audio_lookahead, audio_buf_count).as_str());
 
-            db::log("Synth initialized");
+        let midi_preload = if options.midi_preload && music::music_available() && fatal_error.is_none() {
+            Some(MidiPreload::new(music_names(), options.midi_preload_budget * 1024))
         }
+        else {
+            None
+        };
+
+        let bindings = KeyBindings::load_from_file(bindings::CONTROLS_PATH);
+        let gamma_lut = gamma_lut(options.gamma);
+
+        let press_to_start = options.press_to_start;
+        music::set_music_muted(options.muted);
+        music::set_master_volume(options.master_volume);
+        music::set_music_volume(options.music_volume);
 
-        return MyApp {
+        let screen_size = doom_screen_size();
+        let canvas_size = ((screen_size.0 as u32).next_power_of_two() as i32, (screen_size.1 as u32).next_power_of_two() as i32);
+        db::log(format!("DOOM's screen is {}x{}, uploaded to a {}x{} canvas", screen_size.0, screen_size.1, canvas_size.0, canvas_size.1).as_str());
+
+        let mut app = MyApp {
             time: 0.0,
+            time_scale: 1.0,
+            clock_last: clock_start,
+            clock_stalled_frames: 0,
             mx: 0.0,
-            prev_left: false,
-            prev_right: false,
-            prev_up: false,
-            prev_down: false,
-            canvas_tex: Texture::new(512, 256, false, TextureFormat::RGBA8888).unwrap(),
-            prev_gp_state: GamepadState { button_mask: GamepadButtonMask::none(), left_stick_x: 0, left_stick_y: 0, right_stick_x: 0, right_stick_y: 0 },
-            audio_buf: [[None, None, None], [None, None, None]],
-            audio_queue: [None, None],
+            my: 0.0,
+            canvas_tex: create_canvas(canvas_size, options.canvas_format),
+            front_buf: 0,
+            screen_size,
+            canvas_size,
+            gamma_lut,
+            fb_scratch: vec![0;doom_fb_size()],
+            render_frame: 0,
+            prev_raw_gp_state: [neutral_gp_state();4],
+            active_slot: 0,
+            controller_connected: true,
+            idle_timer: 0.0,
+            auto_advance_timer: 0.0,
+            idle: false,
+            show_heap_stats: false,
+            show_open_files: false,
+            keyboard: VirtualKeyboard::new(),
+            frame_timer: FrameTimer::new(),
+            automap_follow_sent: false,
+            rumble: Rumble::new(),
+            rumble_on: false,
+            input_mask: GamepadButtonMask::none(),
+            select_combo_used: false,
+            audio_buf: [(); 2].map(|_| std::iter::repeat_with(|| None).take(audio_buf_count).collect()),
+            audio_queue: [VecDeque::new(), VecDeque::new()],
+            audio_scratch: [Vec::new(), Vec::new()],
+            audio_upsample_scratch: Vec::new(),
             audio_schedule_time: -1.0,
-            next_buf: 0
+            audio_last_sample: [0;2],
+            audio_crossfade: [None;2],
+            audio_silence_run: 0,
+            filter_state: [0.0;2],
+            audio_underruns: 0,
+            audio_underrun_window: 0.0,
+            next_buf: 0,
+            options,
+            key_queue: KeyQueue::new(),
+            game_state: GameState::DemoScreen,
+            autosave_pending: false,
+            borrowed_quicksave: None,
+            overlay: Overlay::new(),
+            confirm: None,
+            midi_preload,
+            start_gate: if press_to_start { StartGate::WaitRelease } else { StartGate::Open },
+            latency_calibration: false,
+            calibration_flash: 0,
+            music_ducker: Ducker::new(),
+            audio_alloc: AllocTracker::new(),
+            toasts: ToastQueue::new(),
+            soundfonts,
+            soundfont_idx,
+            settings_hud: SettingsHud::new(),
+            bindings,
+            held_button_keys: BTreeMap::new(),
+            keys_down: Vec::new(),
+            held_keys: BTreeSet::new(),
+            key_press_tics: BTreeMap::new(),
+            menu_repeat: KeyRepeat::new(),
+            hud_message_count: 0,
+            level_count: 0,
+            applied_reverb: None,
+            playing_demo: false,
+            input_replay,
+            shutdown_frames: None,
+            released: false,
+            load_error,
+            load_error_time: LOAD_ERROR_SECONDS,
+            fatal_error,
         };
+
+        if !save_dir_writable {
+            app.show_toast("NO MEMORY CARD - SAVES WON'T BE KEPT", 4.0);
+        }
+
+        return app;
+    }
+
+    /// Have DOOM play the given demo at boot, unless the launch args already pick one. DOOM loads NAME.lmp as an extra file if there is one
+    /// (see open_file for where it's looked for), then plays the lump called NAME, which can also come from a WAD
+    fn add_playdemo_arg(doom_args: &mut DoomArgs, name: &str) {
+        if doom_args.value_of("-playdemo").is_some() {
+            db::log(format!("Launch args already play a demo, ignoring playdemo={}", name).as_str());
+            return;
+        }
+
+        let lmp = demo_path(&format!("{}.lmp", name));
+        if io::file_exists(&lmp) {
+            db::log(format!("Playing demo {}", lmp).as_str());
+        }
+        else {
+            db::log(format!("No {}.lmp found, playing demo lump {} from the WADs", name, name.to_ascii_uppercase()).as_str());
+        }
+
+        doom_args.arg("-playdemo").arg(name);
     }
 
-    fn schedule_voice(handle: i32, slot: i32, pan: f32, t: f64) {
+    fn schedule_voice(handle: i32, slot: i32, samplerate: i32, volume: f32, pan: f32, reverb: bool, t: f64) {
         audio::queue_set_voice_param_i(slot, audio::AudioVoiceParam::SampleData, handle, t);
-        audio::queue_set_voice_param_i(slot, audio::AudioVoiceParam::Samplerate, 11025, t);
+        audio::queue_set_voice_param_i(slot, audio::AudioVoiceParam::Samplerate, samplerate, t);
         audio::queue_set_voice_param_i(slot, audio::AudioVoiceParam::LoopEnabled, 0, t);
-        audio::queue_set_voice_param_i(slot, audio::AudioVoiceParam::Reverb, 0, t);
-        audio::queue_set_voice_param_f(slot, audio::AudioVoiceParam::Volume, 1.0, t);
+        audio::queue_set_voice_param_i(slot, audio::AudioVoiceParam::Reverb, reverb as i32, t);
+        audio::queue_set_voice_param_f(slot, audio::AudioVoiceParam::Volume, volume, t);
         audio::queue_set_voice_param_f(slot, audio::AudioVoiceParam::Pitch, 1.0, t);
         audio::queue_set_voice_param_f(slot, audio::AudioVoiceParam::Detune, 0.0, t);
         audio::queue_set_voice_param_f(slot, audio::AudioVoiceParam::Pan, pan, t);
@@ -92,237 +732,1551 @@ impl MyApp {
         audio::queue_start_voice(slot, t);
     }
 
-    fn process_audio(&mut self) {
-        let sample_cnt = 512;
-        let t = self.audio_schedule_time + AUDIO_LOOKAHEAD_TIME;
+    /// Briefly show a message on screen
+    fn show_toast(&mut self, text: &str, seconds: f32) {
+        db::log(format!("Toast: {}", text).as_str());
+
+        if self.options.show_toasts {
+            self.toasts.push(text, seconds);
+        }
+    }
+
+    /// Set the MIDI volume from the current mute, ducking & controller state
+    fn apply_music_volume(&self) {
+        if !music::music_playing() {
+            return;
+        }
+
+        if !self.audio_active() {
+            music::set_playing_volume(0.0);
+        }
+        else if self.options.duck_music {
+            music::set_playing_volume(music::track_volume() * self.music_ducker.gain());
+        }
+        else {
+            music::set_playing_volume(music::track_volume());
+        }
+    }
+
+    /// Switch to the next performance profile. Audio & render options take effect right away, but MIDI preloading only happens at boot
+    fn cycle_profile(&mut self) {
+        let profile = self.options.profile.next();
+        let canvas_format = self.options.canvas_format;
+        self.options.apply_profile(profile);
+        log::set_log_level(self.options.log_level);
+
+        // the new canvas is drawn to on the very next frame, so it's never shown empty
+        if self.options.canvas_format != canvas_format {
+            self.canvas_tex = create_canvas(self.canvas_size, self.options.canvas_format);
+            self.render_frame = 0;
+        }
+        self.options.save_settings();
+
+        self.show_toast(format!("PROFILE: {}", profile.name()).as_str(), 1.5);
+    }
+
+    fn toggle_always_run(&mut self) {
+        self.options.always_run = !self.options.always_run;
+        self.options.save_settings();
+
+        db::log(format!("Always run: {}", if self.options.always_run { "on" } else { "off" }).as_str());
+        self.show_toast(if self.options.always_run { "ALWAYS RUN: ON" } else { "ALWAYS RUN: OFF" }, 1.5);
+    }
+
+    fn toggle_mute(&mut self) {
+        self.options.muted = !self.options.muted;
+        music::set_music_muted(self.options.muted);
+        self.apply_music_volume();
+
+        // sound effects pick up the new volume from the next buffer that gets scheduled
+        self.show_toast(if self.options.muted { "AUDIO MUTED" } else { "AUDIO UNMUTED" }, 1.5);
+
+        if self.options.persist_mute {
+            self.options.save_settings();
+        }
+    }
+
+    /// Step the music volume up or down by 5%, independently of sound effects
+    fn step_music_volume(&mut self, steps: i32) {
+        // same as the master volume, stepping on a fixed grid (twentieths here) keeps the value from drifting
+        let twentieths = ((self.options.music_volume * 20.0).round() as i32 + steps).clamp(0, 20);
+        self.options.music_volume = twentieths as f32 / 20.0;
+
+        music::set_music_volume(self.options.music_volume);
+        self.apply_music_volume();
+
+        db::log(format!("Music volume: {:.2}", self.options.music_volume).as_str());
+        self.settings_hud.show(format!("MUSIC VOLUME: {}%", twentieths * 5).as_str(), self.options.music_volume);
+        self.options.save_settings();
+    }
+
+    /// Raise or lower the master volume by the given number of steps
+    fn step_master_volume(&mut self, steps: i32) {
+        // stepping in whole tenths keeps the value from drifting off the grid over repeated adjustments
+        let tenths = ((self.options.master_volume * 10.0).round() as i32 + steps).clamp(0, 10);
+        self.options.master_volume = tenths as f32 / 10.0;
+
+        music::set_master_volume(self.options.master_volume);
+        self.apply_music_volume();
+
+        db::log(format!("Master volume: {:.1}", self.options.master_volume).as_str());
+        self.settings_hud.show(format!("VOLUME: {}%", tenths * 10).as_str(), self.options.master_volume);
+        self.options.save_settings();
+    }
+
+    /// Advance the time DOOM sees. This follows the real clock, so dropped frames don't slow the game down,
+    /// but if the audio timer stops advancing it switches over to counting frames (picking up from the same time, so it doesn't jump)
+    fn update_clock(&mut self, delta: f32) {
+        if USE_REAL_CLOCK.load(Ordering::Relaxed) {
+            let now = audio::get_time();
+            if now > self.clock_last {
+                self.clock_last = now;
+                self.clock_stalled_frames = 0;
+            }
+            else {
+                self.clock_stalled_frames += 1;
+            }
+
+            if self.clock_stalled_frames < CLOCK_STALL_FRAMES {
+                self.time = game_clock_time() as f32;
+                return;
+            }
+
+            db::log("Audio timer stopped advancing, timing DOOM by frame count");
+            USE_REAL_CLOCK.store(false, Ordering::Relaxed);
+        }
+
+        self.time += delta * self.time_scale;
+        unsafe {
+            TIME = self.time;
+        }
+    }
+
+    /// Step to the next game speed. DOOM runs as many tics as its clock says have passed, so scaling the clock is all it takes
+    fn cycle_time_scale(&mut self) {
+        let idx = TIME_SCALES.iter().position(|&v| v == self.time_scale).map_or(0, |v| (v + 1) % TIME_SCALES.len());
+        self.time_scale = TIME_SCALES[idx];
+
+        // the scaled clock carries on from where it is now, so changing speed doesn't make it jump
+        unsafe {
+            CLOCK_ANCHOR_GAME = game_clock_time();
+            CLOCK_ANCHOR_REAL = real_clock_time();
+            TIME_SCALE = self.time_scale as f64;
+        }
+
+        db::log(format!("Game speed: {}x", self.time_scale).as_str());
+        self.show_toast(format!("GAME SPEED: {}X", self.time_scale).as_str(), 1.5);
+    }
+
+    /// Save what's currently on screen (DOOM's framebuffer, without any host overlays) to the memory card
+    fn capture_screenshot(&mut self) {
+        let fb = match doom_framebuffer() {
+            Some(v) => v,
+            None => {
+                self.show_toast("SCREENSHOT FAILED", 1.5);
+                return;
+            }
+        };
+
+        match screenshot::save_screenshot(fb, self.screen_size.0 as u16, self.screen_size.1 as u16) {
+            Ok(path) => {
+                db::log(format!("Saved screenshot to {}", path).as_str());
+                self.show_toast("SCREENSHOT SAVED", 1.5);
+            }
+            Err(e) => {
+                db::log(format!("Failed saving screenshot: {}", e).as_str());
+                self.show_toast("SCREENSHOT FAILED", 1.5);
+            }
+        }
+    }
+
+    /// Raise or lower the gamma by the given number of steps
+    fn step_gamma(&mut self, steps: i32) {
+        // same as the volume, stepping in whole tenths keeps the value on the grid (& lands exactly on 1, which skips the gamma pass)
+        let tenths = ((self.options.gamma * 10.0).round() as i32 + steps)
+            .clamp((options::MIN_GAMMA * 10.0) as i32, (options::MAX_GAMMA * 10.0) as i32);
+        self.options.gamma = tenths as f32 / 10.0;
+        self.gamma_lut = gamma_lut(self.options.gamma);
+
+        db::log(format!("Gamma: {:.1}", self.options.gamma).as_str());
+        let fill = (self.options.gamma - options::MIN_GAMMA) / (options::MAX_GAMMA - options::MIN_GAMMA);
+        self.settings_hud.show(format!("GAMMA: {:.1}", self.options.gamma).as_str(), fill);
+        self.options.save_settings();
+    }
+
+    /// Keys a button bound to the given keys should press right now (see resolve_keys_for)
+    fn resolve_keys(&self, keys: &[i32]) -> Vec<i32> {
+        resolve_keys_for(keys, self.options.a_button_mode, unsafe { doom_is_menu_active() } != 0)
+    }
+
+    fn audio_lookahead(&self) -> f64 {
+        self.options.audio_lookahead + self.options.audio_latency_comp
+    }
+
+    fn process_audio(&mut self) -> usize {
+        let buffer_size = usize::try_from(unsafe { doom_get_sound_buffer_size() }).unwrap();
+        debug_assert!(buffer_size % 2 == 0, "DOOM's sound buffer should hold whole stereo frames, but has {} samples", buffer_size);
+        let sample_cnt = buffer_size / 2;
+        let t = self.audio_schedule_time + self.audio_lookahead();
+        let resample_mode = self.options.audio_resample;
 
         // we need to "unzip" interleaved LR audio into two mono buffers
-        let mut data_l: Vec<i16> = vec![0;sample_cnt];
-        let mut data_r: Vec<i16> = vec![0;sample_cnt];
-        
+        // the scratch buffers hold on to their capacity, so once they've grown this doesn't allocate
+        // (one extra sample of room is kept for the next chunk's first sample, which gets spliced on in next_audio_chunk)
+        for scratch in &mut self.audio_scratch {
+            scratch.clear();
+            scratch.reserve(sample_cnt + 1);
+        }
+        let [data_l, data_r] = &mut self.audio_scratch;
+
         // get audio buffer from DOOM
         unsafe {
             let audio_buf_ptr = doom_get_sound_buffer();
-            let audio_buf = slice_from_raw_parts(audio_buf_ptr, 1024);
+            let audio_buf = &*slice_from_raw_parts(audio_buf_ptr, buffer_size);
+
+            // DOOM's mix is quiet, so it gets boosted (4x by default) - clamped rather than wrapped, so loud scenes clip instead of flipping polarity & popping
+            let gain = self.options.audio_gain;
+            for frame in audio_buf.chunks_exact(2) {
+                data_l.push(resample::apply_gain(frame[0], gain));
+                data_r.push(resample::apply_gain(frame[1], gain));
+            }
+        }
+
+        if self.options.lowpass_cutoff > 0.0 {
+            let alpha = resample::lowpass_alpha(self.options.lowpass_cutoff);
+            let [state_l, state_r] = &mut self.filter_state;
+            resample::lowpass(data_l, state_l, alpha);
+            resample::lowpass(data_r, state_r, alpha);
+        }
+
+        if self.options.duck_music {
+            let peak = data_l.iter().chain(data_r.iter()).map(|v| v.unsigned_abs()).max().unwrap_or(0);
+            self.music_ducker.feed(peak as f32 / 32768.0, self.options.duck_threshold, self.options.duck_amount);
+        }
+
+        // in forced mono, both channels get mixed down into the left buffer & played centered on a single voice
+        if self.audio_alloc.forced_mono {
+            for (l, r) in data_l.iter_mut().zip(data_r.iter()) {
+                *l = ((*l as i32 + *r as i32) / 2) as i16;
+            }
+        }
+
+        // once there's been nothing but silence for a while (like while paused), nothing is submitted until there's sound again.
+        // the schedule still moves on (so this isn't treated as an underrun), & the sound fades in over the first buffer so it doesn't click
+        let silent = data_l.iter().chain(data_r.iter()).all(|v| v.unsigned_abs() <= AUDIO_SILENCE_LEVEL);
+        if silent {
+            self.audio_silence_run += 1;
+            if self.audio_silence_run > AUDIO_SILENCE_BUFFERS {
+                return sample_cnt;
+            }
+        }
+        else {
+            if self.audio_silence_run > AUDIO_SILENCE_BUFFERS {
+                crossfade_from(data_l, 0, sample_cnt);
+                crossfade_from(data_r, 0, sample_cnt);
+            }
+            self.audio_silence_run = 0;
+        }
+
+        // we have a rotating buffer of audio samples we use to upload audio data
+        // NOTE: this will automatically deallocate the previous buffers here
+        // (create_s16 copies the sample data into audio memory, so the Vecs here are only staging & can be reused as soon as it returns -
+        // it's the AudioSample which has to outlive playback, since dropping it frees the audio memory even if a voice is still reading from it)
+
+        // this is a little tricky:
+        // basically, instead of queueing audio chunks right away, we actually stuff them into a buffer and wait
+        // then, when we get the next buffer, we actually take its first sample and append it to the start of the LAST buffer and submit that
+        // this is all to make DreamBox's 2-tap sampling play nicely - b/c at the end of one of our submitted samples, DreamBox doesn't take the next sample we queue up into account,
+        // so there's a single sample of aliasing in between every single buffer we submit and it ends up sounding scratchy
+        // this fixes that by basically making each buffer end with the next buffer's starting sample (splice_seam, which can also blend a longer tail into it - see AUDIO_SEAM_OVERLAP)
+        // (in ResampleMode::Linear the same boundary sample is also what the software upsampler interpolates towards)
+        // with a deeper audio_queue_depth, more chunks wait in the queue first - the splice is always between a chunk & the one right behind it
+
+        let mut alloc_ok = true;
+        if self.audio_alloc.forced_mono {
+            alloc_ok &= self.queue_audio_chunk(0, 0.0, t, resample_mode);
+        }
+        else {
+            // DOOM has already mixed & panned every sound into these two channels, so all that can be adjusted here is how far apart they are
+            let width = self.options.stereo_width;
+            alloc_ok &= self.queue_audio_chunk(0, -width, t, resample_mode);
+            alloc_ok &= self.queue_audio_chunk(1, width, t, resample_mode);
+        }
+
+        self.next_buf += 1;
+
+        self.track_audio_allocation(alloc_ok, t);
+
+        sample_cnt
+    }
+
+    /// Move the channel's scratch buffer onto its queue, & once the queue is deeper than audio_queue_depth submit the oldest chunk
+    /// (see next_audio_chunk). The submitted chunk's Vec then becomes the new scratch buffer <br/>
+    /// Returns false if the audio sample couldn't be allocated (in which case the chunk is dropped)
+    fn queue_audio_chunk(&mut self, channel: usize, pan: f32, t: f64, resample_mode: ResampleMode) -> bool {
+        let chunk = std::mem::take(&mut self.audio_scratch[channel]);
+        let depth = self.options.audio_queue_depth;
+        let oldest = match next_audio_chunk(&mut self.audio_queue[channel], chunk, depth, &mut self.audio_crossfade[channel], &mut self.audio_last_sample[channel]) {
+            Some(v) => v,
+            None => return true,
+        };
 
-            for i in 0..sample_cnt {
-                data_l[i] = (&*audio_buf)[i * 2] << 2;
-                data_r[i] = (&*audio_buf)[i * 2 + 1] << 2;
+        let samples = if resample_mode.factor() > 1 {
+            resample::upsample_linear(&oldest, resample_mode.factor(), &mut self.audio_upsample_scratch);
+            &self.audio_upsample_scratch
+        }
+        else {
+            &oldest
+        };
+        let result = AudioSample::create_s16(samples, resample_mode.samplerate());
+        self.audio_scratch[channel] = oldest;
+
+        match result {
+            Ok(sample) => {
+                let handle = sample.handle;
+                let buf_count = self.audio_buf[channel].len();
+                self.audio_buf[channel][self.next_buf % buf_count] = Some(sample);
+                // sound effects still play out in real time, so they're muted while the game runs at any other speed rather than piling up or dragging behind it
+                let volume = if self.options.muted || self.time_scale != 1.0 { 0.0 } else { self.options.master_volume };
+                MyApp::schedule_voice(handle, channel as i32, resample_mode.samplerate(), volume, pan, self.options.reverb_amount > 0.0, t);
+                true
             }
+            Err(_) => false,
+        }
+    }
+
+    /// Recover from the audio schedule falling behind real time (usually a stall while loading). <br/>
+    /// The oldest queued chunk on each voice is stale by now & gets dropped, then the schedule jumps forward to the present,
+    /// & the next chunk to be submitted fades in from the last good buffer so there's no hard click at the seam
+    fn resync_audio(&mut self) {
+        for channel in 0..2 {
+            self.audio_queue[channel].pop_front();
+            self.audio_crossfade[channel] = Some(self.audio_last_sample[channel]);
+        }
+
+        self.audio_schedule_time = audio::get_time();
+        self.audio_underruns += 1;
+    }
+
+    /// Log how many underruns happened over the last interval, if there were any
+    fn track_audio_underruns(&mut self) {
+        let now = audio::get_time();
+        if now - self.audio_underrun_window < AUDIO_UNDERRUN_LOG_INTERVAL {
+            return;
+        }
+
+        if self.audio_underruns > 0 {
+            db::log(format!("Audio: {} underrun(s) in the last minute", self.audio_underruns).as_str());
+        }
+
+        self.audio_underruns = 0;
+        self.audio_underrun_window = now;
+    }
+
+    /// Switch to a single mono voice after repeated allocation failures, & back to stereo after a sustained run without any
+    fn track_audio_allocation(&mut self, alloc_ok: bool, t: f64) {
+        // a failed allocation just drops the chunk (the schedule still moves on, & the slot it would have gone in keeps its old sample),
+        // so it's a glitch rather than a crash. only the first of a run is logged, since without the mono fallback it can happen every buffer
+        match self.audio_alloc.record(alloc_ok, self.options.audio_mono_fallback) {
+            AllocChange::None => {}
+            AllocChange::Failing => db::log("Failed creating audio sample, dropping audio chunk"),
+            AllocChange::Recovered(failures) => db::log(format!("Audio allocations recovered after dropping {} chunks", failures).as_str()),
+            AllocChange::FallBackToMono => {
+                db::log("Repeated audio allocation failures, falling back to mono");
+                self.show_toast("AUDIO: MONO (LOW MEMORY)", 2.0);
+                self.audio_queue[1].clear();
+                audio::queue_stop_voice(1, t);
+            }
+            AllocChange::BackToStereo => {
+                db::log("Audio allocations recovered, switching back to stereo");
+                self.show_toast("AUDIO: STEREO", 2.0);
+            }
+        }
+    }
+
+    fn on_game_state_changed(&mut self, prev: GameState, new: GameState) {
+        // DOOM's tic count alongside the time on DOOM's clock (real time since boot, unless the game speed was changed or the real clock isn't available),
+        // so tools reading the log can line game time up with real time
+        let tic = unsafe { doom_get_tic() };
+        db::log(format!("Game state: {} -> {} at tic {} ({:.3}s on DOOM's clock)", prev.name(), new.name(), tic, self.time).as_str());
+
+        self.auto_advance_timer = 0.0;
+
+        match (prev, new) {
+            (GameState::Level, GameState::Intermission) => {
+                // level completed - we hold off on saving until the player has actually arrived at the next level,
+                // since DOOM only allows saving while in GS_LEVEL
+                self.autosave_pending = self.options.autosave;
+            }
+            (_, GameState::Level) if self.autosave_pending => {
+                self.autosave_pending = false;
+                self.queue_autosave();
+            }
+            (_, GameState::DemoScreen) => {
+                self.autosave_pending = false;
+            }
+            _ => {
+            }
+        }
+    }
+
+    fn queue_autosave(&mut self) {
+        db::log(format!("Level complete, autosaving to slot {}", self.options.autosave_slot).as_str());
+
+        // point quicksave at the autosave slot first. the player's own slot is put back once the save has gone through (see restore_quicksave_slot),
+        // so their quicksaves & quickloads don't end up on the autosave. their slot's description is in its save file, so that comes back by itself
+        if self.borrowed_quicksave.is_none() {
+            self.borrowed_quicksave = Some(BorrowedQuicksave { slot: unsafe { doom_get_quicksave_slot() }, sent_tic: None });
+        }
+        unsafe {
+            doom_set_quicksave_slot(self.options.autosave_slot, b"AUTOSAVE\0".as_ptr() as *const c_char);
+        }
+
+        self.queue_quicksave(self.options.autosave_slot);
+    }
+
+    /// Point quicksave back at the player's own slot once an autosave is done with it
+    fn restore_quicksave_slot(&mut self) {
+        let input_tic = unsafe { doom_get_input_tic() };
+        if let Some(slot) = quicksave_restore_due(&mut self.borrowed_quicksave, !self.key_queue.is_empty(), input_tic) {
+            db::log(format!("Autosave done, quicksave slot back to {}", slot).as_str());
+            unsafe { doom_set_quicksave_slot(slot, b"\0".as_ptr() as *const c_char) };
+        }
+    }
+
+    /// Quicksave requested by the player
+    fn manual_quicksave(&mut self) {
+        let slot = unsafe { doom_get_quicksave_slot() };
+
+        if slot < 0 {
+            // no quicksave slot yet - F6 will just open DOOM's save menu so the player can pick one
+            self.key_queue.push_tap(input::KEY_F6, 0);
+            return;
+        }
+
+        if self.options.confirm_overwrite && save_slot_occupied(slot) {
+            self.confirm = Some(Confirmation {
+                prompt: format!("Overwrite save in slot {}?", slot + 1),
+                action: ConfirmAction::Quicksave { slot },
+            });
+            return;
+        }
+
+        self.queue_quicksave(slot);
+    }
+
+    fn queue_quicksave(&mut self, slot: i32) {
+        db::log(format!("Quicksave to slot {}", slot).as_str());
+
+        // press F6 & answer yes to DOOM's own overwrite prompt, since there's no way to press Y on the gamepad
+        self.key_queue.push_tap(input::KEY_F6, 0);
+        self.key_queue.push_tap(input::KEY_Y, 2);
+    }
+
+    /// Quickload requested by the player
+    fn manual_quickload(&mut self) {
+        let slot = unsafe { doom_get_quicksave_slot() };
+
+        if slot < 0 {
+            // nothing has been quicksaved yet - F9 will just show DOOM's message saying so
+            db::log("Quickload requested, but there's no quicksave slot yet");
+            self.key_queue.push_tap(input::KEY_F9, 0);
+            return;
+        }
+
+        if self.options.confirm_quickload {
+            self.confirm = Some(Confirmation {
+                prompt: format!("Load the quicksave in slot {}?", slot + 1),
+                action: ConfirmAction::Quickload,
+            });
+            return;
+        }
+
+        self.queue_quickload();
+    }
+
+    fn queue_quickload(&mut self) {
+        db::log(format!("Quickload from slot {}", unsafe { doom_get_quicksave_slot() }).as_str());
+
+        // press F9 & answer yes to DOOM's own prompt, same as quicksave
+        self.key_queue.push_tap(input::KEY_F9, 0);
+        self.key_queue.push_tap(input::KEY_Y, 2);
+    }
+
+    fn run_confirm_action(&mut self, action: ConfirmAction) {
+        match action {
+            ConfirmAction::Quicksave { slot } => self.queue_quicksave(slot),
+            ConfirmAction::Quickload => self.queue_quickload(),
+        }
+    }
+
+    /// Switch to the next soundfont & restart the current track with it. If it can't be loaded, the previous one is put back
+    fn cycle_soundfont(&mut self) {
+        if self.soundfonts.len() < 2 {
+            self.show_toast("NO OTHER SOUNDFONTS", 1.5);
+            return;
+        }
+
+        let next = (self.soundfont_idx + 1) % self.soundfonts.len();
+        let name = self.soundfonts[next].rsplit('/').next().unwrap_or_default().to_ascii_uppercase();
+
+        match load_soundfont(&self.soundfonts[next]) {
+            Ok(()) => {
+                db::log(format!("Switched to soundfont {}", self.soundfonts[next]).as_str());
+                self.soundfont_idx = next;
+                music::set_music_available(true);
+                self.show_toast(format!("SOUNDFONT: {}", name).as_str(), 2.0);
+            }
+            Err((e, _)) => {
+                db::log(format!("Failed switching soundfont: {}", e).as_str());
+                self.show_toast(format!("COULDN'T LOAD {}", name).as_str(), 2.0);
+
+                // with nothing working to go back to, the next press moves on to the one after instead
+                if !music::music_available() {
+                    self.soundfont_idx = next;
+                    return;
+                }
+
+                if let Err((e, _)) = load_soundfont(&self.soundfonts[self.soundfont_idx]) {
+                    db::log(format!("Failed restoring the previous soundfont, music is disabled: {}", e).as_str());
+                    music::set_music_available(false);
+                    music::set_music_playing(false);
+                    return;
+                }
+            }
+        }
+
+        // recordings don't go through the synth, so they can keep playing
+        if music::is_streamed_playing() {
+            return;
+        }
+
+        if let Some(track) = music::current_track() {
+            music::set_current_track(None);
+            let track = CString::new(track).unwrap();
+            unsafe {
+                doom_playmus(track.as_ptr(), 1);
+            }
+        }
+    }
+
+    /// Step through the debug readouts: none, FPS counter, FPS counter & memory usage, then all of those & the files DOOM has open
+    fn cycle_readouts(&mut self) {
+        match (self.options.show_fps, self.show_heap_stats, self.show_open_files) {
+            (false, _, _) => {
+                self.options.show_fps = true;
+                self.show_heap_stats = false;
+                self.show_open_files = false;
+            }
+            (true, false, _) => self.show_heap_stats = true,
+            (true, true, false) => self.show_open_files = true,
+            (true, true, true) => {
+                self.options.show_fps = false;
+                self.show_heap_stats = false;
+                self.show_open_files = false;
+            }
+        }
+    }
+
+    /// Run DOOM's framebuffer through whichever post-processing steps are active, into fb_scratch. <br/>
+    /// Returns false if none are, in which case fb_scratch is left alone & the framebuffer can be uploaded as-is. <br/>
+    /// With an RGB565 canvas, frames always need converting, so the gamma curve is applied along the way
+    fn postprocess_frame(&mut self, fb: &[u8]) -> bool {
+        let channels = DOOM_FB_CHANNELS as usize;
+
+        match self.options.canvas_format {
+            CanvasFormat::Rgba8888 => {
+                if self.options.gamma == 1.0 {
+                    return false;
+                }
+
+                for (dst, src) in self.fb_scratch.chunks_exact_mut(channels).zip(fb.chunks_exact(channels)) {
+                    dst[0] = self.gamma_lut[src[0] as usize];
+                    dst[1] = self.gamma_lut[src[1] as usize];
+                    dst[2] = self.gamma_lut[src[2] as usize];
+                    dst[3] = src[3];
+                }
+            }
+            CanvasFormat::Rgb565 => {
+                for (dst, src) in self.fb_scratch.chunks_exact_mut(2).zip(fb.chunks_exact(channels)) {
+                    let lut = &self.gamma_lut;
+                    dst.copy_from_slice(&pack_rgb565(lut[src[0] as usize], lut[src[1] as usize], lut[src[2] as usize]).to_le_bytes());
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Log the HUD message if a new one went up this frame, since DOOM only draws them on screen. <br/>
+    /// If several went up in one frame only the last is logged
+    fn log_hud_message(&mut self) {
+        let count = unsafe { doom_get_message_count() };
+        if count == self.hud_message_count {
+            return;
+        }
+        self.hud_message_count = count;
+
+        let message = unsafe { doom_get_message() };
+        if !message.is_null() {
+            log::hud_message(&unsafe { CStr::from_ptr(message) }.to_string_lossy());
+        }
+    }
+
+    /// Advance the start gate with this frame's input <br/>
+    /// Returns true once the gate is open & input should be handled normally
+    fn update_start_gate(&mut self, state: GamepadState) -> bool {
+        match self.start_gate {
+            StartGate::Open => return true,
+            StartGate::WaitRelease => {
+                if state.button_mask.is_none() {
+                    self.start_gate = StartGate::WaitPress;
+                }
+            }
+            StartGate::WaitPress => {
+                if !state.button_mask.is_none() {
+                    // the press which opens the gate is swallowed, & stays hidden from DOOM (and the Select shortcuts) until released
+                    self.start_gate = StartGate::Open;
+                    self.input_mask = state.button_mask;
+                    self.select_combo_used = true;
+                }
+            }
+        }
+
+        false
+    }
+
+    fn draw_start_gate(&mut self) {
+        if self.start_gate != StartGate::Open {
+            self.overlay.draw_text_centered(overlay::SCREEN_HEIGHT * 0.75, 2.0, "PRESS ANY BUTTON", Color32::new(255, 255, 255, 255));
+        }
+    }
+
+    /// Stop all audio & music, & release the audio samples & screen textures. Nothing can be drawn or played after this. Does nothing if it's already been called <br/>
+    /// The samples are freed straight away, so any voices still playing them are stopped first
+    fn shutdown(&mut self) {
+        if self.released {
+            return;
+        }
+        self.released = true;
+
+        let t = audio::get_time();
+        for (slot, queue) in self.audio_queue.iter_mut().enumerate() {
+            queue.clear();
+            audio::queue_stop_voice(slot as i32, t);
+        }
+        for bufs in &mut self.audio_buf {
+            bufs.iter_mut().for_each(|v| *v = None);
+        }
+
+        music::set_music_playing(false);
+        music::stop_streamed();
+        audio::set_midi_volume(0.0);
+        self.set_rumble(false);
+
+        self.canvas_tex.clear();
+        db::log("Released audio & textures");
+    }
+
+    /// Run in place of update once DOOM has quit: silences the audio, shows a goodbye message for a moment, then stops the vsync loop
+    fn update_shutdown(&mut self) {
+        let frames = match self.shutdown_frames {
+            Some(v) => v,
+            None => {
+                db::log(format!("DOOM exited (code {}), shutting down", EXIT_CODE.load(Ordering::Relaxed)).as_str());
+
+                // whatever is already scheduled plays out, but nothing new gets queued & the voices stop once the last buffer finishes
+                let t = self.audio_schedule_time + self.audio_lookahead();
+                for (slot, queue) in self.audio_queue.iter_mut().enumerate() {
+                    queue.clear();
+                    audio::queue_stop_voice(slot as i32, t);
+                }
+
+                music::set_music_playing(false);
+                music::stop_streamed();
+                audio::set_midi_volume(0.0);
+                self.set_rumble(false);
+                0
+            }
+        };
+
+        if frames >= SHUTDOWN_FRAMES {
+            self.shutdown();
+            db::log("Halted");
+            vdp::set_vsync_handler(None);
+            return;
+        }
+
+        self.shutdown_frames = Some(frames + 1);
+
+        vdp::clear_color(Color32::new(0, 0, 0, 255));
+        self.overlay.draw_text_centered(overlay::SCREEN_HEIGHT * 0.5 - overlay::LINE_HEIGHT, 2.0, "THANKS FOR PLAYING", Color32::new(255, 255, 255, 255));
+        self.overlay.flush();
+    }
+
+    /// Handle input for host-side UI (confirmation prompts & Select button combos) <br/>
+    /// Returns the gamepad state which should be forwarded on to DOOM
+    fn handle_ui_input(&mut self, state: GamepadState, prev: GamepadState) -> GamepadState {
+        let pressed = |button: GamepadButton| state.is_pressed(button) && !prev.is_pressed(button);
+        let newly_pressed = state.button_mask & !prev.button_mask;
+
+        if self.confirm.is_some() {
+            if pressed(GamepadButton::A) {
+                let confirm = self.confirm.take().unwrap();
+                self.run_confirm_action(confirm.action);
+            }
+            else if pressed(GamepadButton::B) {
+                self.confirm = None;
+            }
+
+            // whatever is still held when the prompt closes shouldn't leak through to DOOM
+            self.input_mask = state.button_mask;
+            return neutral_gp_state();
+        }
+
+        if self.latency_calibration {
+            self.handle_latency_calibration_input(pressed);
+            self.input_mask = state.button_mask;
+            return neutral_gp_state();
+        }
+
+        if self.keyboard.is_open() {
+            // Select+A closes the keyboard again, same as B
+            if pressed(GamepadButton::B) || (state.is_pressed(GamepadButton::Select) && pressed(GamepadButton::A)) {
+                self.keyboard.set_open(false);
+            }
+            else {
+                self.handle_keyboard_input(pressed);
+            }
+
+            self.input_mask = state.button_mask;
+            return neutral_gp_state();
+        }
+
+        // Select acts as a modifier for host-side shortcuts, and only opens the automap if it's released without being combined with anything
+        if state.is_pressed(GamepadButton::Select) {
+            if !newly_pressed.is_none() {
+                self.select_combo_used = true;
+            }
+
+            // the configurable combos come first, so they win over whatever else is on the same button
+            if pressed(self.options.quicksave_button) {
+                self.manual_quicksave();
+            }
+            else if pressed(self.options.quickload_button) {
+                self.manual_quickload();
+            }
+            else if self.options.soundfont_button.is_some_and(pressed) {
+                self.cycle_soundfont();
+            }
+            else if self.options.speed_button.is_some_and(pressed) {
+                self.cycle_time_scale();
+            }
+            else if pressed(self.options.mute_button) {
+                self.toggle_mute();
+            }
+            else if pressed(GamepadButton::X) {
+                self.cycle_profile();
+            }
+            else if pressed(GamepadButton::B) {
+                self.capture_screenshot();
+            }
+            else if pressed(GamepadButton::A) {
+                self.keyboard.set_open(true);
+            }
+            else if pressed(GamepadButton::L2) {
+                self.step_music_volume(-1);
+            }
+            else if pressed(GamepadButton::R2) {
+                self.step_music_volume(1);
+            }
+            else if pressed(GamepadButton::R3) {
+                self.cycle_readouts();
+            }
+            else if pressed(GamepadButton::L3) {
+                self.latency_calibration = true;
+            }
+            else if pressed(GamepadButton::Start) {
+                self.toggle_always_run();
+            }
+            else if pressed(GamepadButton::Left) {
+                self.step_gamma(-1);
+            }
+            else if pressed(GamepadButton::Right) {
+                self.step_gamma(1);
+            }
+            else if pressed(GamepadButton::Up) {
+                self.step_master_volume(1);
+            }
+            else if pressed(GamepadButton::Down) {
+                self.step_master_volume(-1);
+            }
+
+            self.input_mask |= newly_pressed;
+        }
+        else if prev.is_pressed(GamepadButton::Select) {
+            if !self.select_combo_used {
+                self.key_queue.push_tap(input::KEY_TAB, 0);
+            }
+            self.select_combo_used = false;
+        }
+
+        // buttons used for combos stay hidden from DOOM until they're released
+        self.input_mask &= state.button_mask;
+
+        let mut forwarded = state;
+        forwarded.button_mask = state.button_mask & !self.input_mask;
+        forwarded.button_mask.unset(GamepadButton::Select);
+        forwarded
+    }
+
+    fn draw_load_error(&mut self, delta: f32) {
+        if let Some(error) = &self.load_error {
+            self.overlay.draw_panel(2.0, &[
+                (error, Color32::new(255, 64, 64, 255)),
+                ("", Color32::new(0, 0, 0, 0)),
+                ("SEE THE LOG FOR DETAILS", Color32::new(255, 255, 255, 255)),
+            ]);
+
+            self.load_error_time -= delta;
+            if self.load_error_time <= 0.0 {
+                self.load_error = None;
+            }
+        }
+    }
+
+    /// Run in place of update when DOOM couldn't be started, showing what went wrong
+    fn draw_fatal_error(&mut self) {
+        if let Some(error) = &self.fatal_error {
+            draw_error_screen(&mut self.overlay, error, "ADD AN IWAD (SUCH AS DOOM1.WAD) & REBUILD");
+        }
+    }
+
+    fn draw_confirmation(&mut self) {
+        if let Some(confirm) = &self.confirm {
+            self.overlay.draw_panel(2.0, &[
+                (&confirm.prompt, Color32::new(255, 255, 255, 255)),
+                ("", Color32::new(0, 0, 0, 0)),
+                ("A: YES   B: NO", Color32::new(255, 255, 0, 255)),
+            ]);
+        }
+    }
+
+    /// Handle input while the on-screen keyboard is open. Keys are typed through the key queue, so they reach DOOM the same way as any other synthesized key
+    fn handle_keyboard_input(&mut self, pressed: impl Fn(GamepadButton) -> bool) {
+        if pressed(GamepadButton::Left) {
+            self.keyboard.move_cursor(-1, 0);
+        }
+        else if pressed(GamepadButton::Right) {
+            self.keyboard.move_cursor(1, 0);
+        }
+        else if pressed(GamepadButton::Up) {
+            self.keyboard.move_cursor(0, -1);
+        }
+        else if pressed(GamepadButton::Down) {
+            self.keyboard.move_cursor(0, 1);
+        }
+
+        if pressed(GamepadButton::A) {
+            self.key_queue.push_tap(self.keyboard.selected_key(), 0);
+        }
+    }
+
+    /// Handle input while the audio latency calibration screen is open
+    fn handle_latency_calibration_input(&mut self, pressed: impl Fn(GamepadButton) -> bool) {
+        if pressed(GamepadButton::A) {
+            // the sound goes through DOOM's mixer like any other, so it's subject to the same latency as gameplay sounds
+            unsafe {
+                doom_start_sound(SFX_PISTOL);
+            }
+            self.calibration_flash = CALIBRATION_FLASH_FRAMES;
+        }
+
+        let comp = &mut self.options.audio_latency_comp;
+        if pressed(GamepadButton::Up) {
+            *comp = (*comp + LATENCY_COMP_STEP).min(options::MAX_LATENCY_COMP);
+        }
+        else if pressed(GamepadButton::Down) {
+            *comp = (*comp - LATENCY_COMP_STEP).max(options::MIN_LATENCY_COMP);
+        }
+
+        if pressed(GamepadButton::B) {
+            self.latency_calibration = false;
+            self.options.save_settings();
+            self.show_toast(format!("AUDIO LATENCY SAVED: {:+.3}S", self.options.audio_latency_comp).as_str(), 2.0);
+        }
+    }
+
+    fn draw_latency_calibration(&mut self) {
+        if !self.latency_calibration {
+            return;
+        }
+
+        if self.calibration_flash > 0 {
+            self.calibration_flash -= 1;
+            self.overlay.draw_rect(0.0, 0.0, overlay::SCREEN_WIDTH, overlay::SCREEN_HEIGHT, Color32::new(255, 255, 255, 255));
+        }
+
+        let latency = format!("AUDIO LATENCY: {:+.3}S", self.options.audio_latency_comp);
+        self.overlay.draw_panel(2.0, &[
+            (&latency, Color32::new(255, 255, 255, 255)),
+            ("", Color32::new(0, 0, 0, 0)),
+            ("A: TEST  UP/DOWN: ADJUST  B: DONE", Color32::new(255, 255, 0, 255)),
+        ]);
+    }
+
+    /// Size of the screen quad in NDC, as (x, y) extents either side of center
+    fn quad_extents(turns: usize, pillarbox: bool, target_aspect: f32) -> (f32, f32) {
+        // when rotated by 90 or 270 degrees, the image's width runs along the display's height,
+        // so the quad always gets shrunk to keep the image from being stretched (there's no sensible way to fill the screen with it anyway)
+        let rotated = turns % 2 == 1;
+        if !rotated && !pillarbox {
+            return (1.0, 1.0);
+        }
+
+        // ratio of the quad's NDC width to its height which makes the image come out at the target aspect
+        let ratio = if rotated { 1.0 / (target_aspect * DISPLAY_ASPECT) } else { target_aspect / DISPLAY_ASPECT };
+
+        // pillarbox when the image is narrower than the display, letterbox when it's wider
+        if ratio <= 1.0 { (ratio, 1.0) } else { (1.0, 1.0 / ratio) }
+    }
+
+    /// The quad DOOM's image is drawn on. `max_uv` is how much of the canvas the image covers
+    fn fullscreen_quad(options: &Options, max_uv: (f32, f32)) -> [PackedVertex;6] {
+        let turns = options.rotation.quarter_turns();
+        let (extent_x, extent_y) = MyApp::quad_extents(turns, options.pillarbox, options.target_aspect);
+
+        // screen corners, listed clockwise starting from the top left to match quad_uvs
+        let corners = [(-extent_x, extent_y), (extent_x, extent_y), (extent_x, -extent_y), (-extent_x, -extent_y)];
+        let uvs = quad_uvs(turns, options.flip_h, options.flip_v, max_uv);
+
+        let vtx = |corner: usize| {
+            let (x, y) = corners[corner];
+            let (u, v) = uvs[corner];
+            PackedVertex::new(Vector4::new(x, y, 0.0, 1.0), Vector2::new(u, v), Color32::new(255, 255, 255, 255), Color32::new(0, 0, 0, 0))
+        };
+
+        [
+            vtx(3), vtx(2), vtx(0),
+            vtx(0), vtx(2), vtx(1),
+        ]
+    }
+
+    /// Rotate & mirror stick input to match the image
+    fn orient_sticks(&self, state: &mut GamepadState) {
+        if self.options.rotate_input {
+            let turns = self.options.rotation.quarter_turns();
+            (state.left_stick_x, state.left_stick_y) = input::rotate_stick(state.left_stick_x, state.left_stick_y, turns);
+            (state.right_stick_x, state.right_stick_y) = input::rotate_stick(state.right_stick_x, state.right_stick_y, turns);
+        }
+        if self.options.flip_h {
+            // keep turning & strafing in the same direction as the mirrored view
+            state.left_stick_x = state.left_stick_x.saturating_neg();
+            state.right_stick_x = state.right_stick_x.saturating_neg();
+        }
+    }
+
+    /// Read every controller slot, & make the first connected one the active controller <br/>
+    /// Returns the active controller's state along with its state on the previous frame. Every slot's previous state is kept up to date,
+    /// so switching controllers doesn't look like a bunch of buttons were just pressed or released
+    fn read_gamepads(&mut self) -> (GamepadState, GamepadState) {
+        let mut states = [neutral_gp_state();4];
+        let mut first_connected = None;
+
+        for (i, slot) in GAMEPAD_SLOTS.iter().enumerate() {
+            let gp = Gamepad::new(*slot);
+            if gp.is_connected() {
+                states[i] = gp.read_state();
+                self.orient_sticks(&mut states[i]);
+                first_connected = first_connected.or(Some(i));
+            }
+        }
+
+        // with nothing connected, stay on the last controller (which just reads as neutral) until one shows up
+        if let Some(slot) = first_connected {
+            if slot != self.active_slot {
+                self.set_rumble(false);
+                db::log(format!("Active controller changed to slot {}", GAMEPAD_SLOT_NAMES[slot]).as_str());
+                self.active_slot = slot;
+            }
+        }
+
+        if first_connected.is_some() != self.controller_connected {
+            if first_connected.is_some() {
+                self.on_controller_reconnected();
+            }
+            else {
+                self.on_controller_disconnected();
+            }
+        }
+
+        let prev = self.prev_raw_gp_state[self.active_slot];
+        self.prev_raw_gp_state = states;
+        (states[self.active_slot], prev)
+    }
+
+    /// Turn the active controller's motor on or off
+    fn set_rumble(&mut self, on: bool) {
+        if on != self.rumble_on {
+            Gamepad::new(GAMEPAD_SLOTS[self.active_slot]).set_rumble(on);
+            self.rumble_on = on;
+        }
+    }
+
+    /// Send reverb_amount to the reverb unit whenever it's changed. DOOM has no idea of room acoustics & pre-mixes every sound effect together,
+    /// so it's one amount for everything: music & the sound effect mix both go through the reverb unit while it's above 0. <br/>
+    /// Sound effect voices pick it up as they're scheduled (every buffer) & recorded music when the next track starts
+    fn update_reverb(&mut self) {
+        let amount = self.options.reverb_amount;
+        if self.applied_reverb == Some(amount) {
+            return;
+        }
+        self.applied_reverb = Some(amount);
+
+        audio::set_reverb(REVERB_ROOM_SIZE, REVERB_DAMPING, REVERB_WIDTH, amount, 1.0);
+        music::set_music_reverb(amount > 0.0);
+    }
+
+    /// Run the rumble motor for any pulse in progress. Nothing rumbles while DOOM isn't in a level, or with no controller connected (or while idle)
+    fn update_rumble(&mut self, delta: f32) {
+        let in_level = self.game_state == GameState::Level && unsafe { doom_is_paused() } == 0;
+        if !self.options.rumble_enabled || !self.audio_active() || !in_level {
+            self.rumble.reset();
+        }
+
+        let on = self.rumble.update(delta);
+        self.set_rumble(on);
+    }
+
+    /// Pause DOOM (unless it's already paused) & silence the music. New audio stops being scheduled until a controller is plugged back in
+    fn on_controller_disconnected(&mut self) {
+        db::log("No controller connected, pausing");
+        self.controller_connected = false;
+        self.pause_game();
+        self.apply_music_volume();
+    }
+
+    /// Press use every auto_advance_delay seconds on the intermission & finale screens, which otherwise wait for the player to press it. <br/>
+    /// Nothing is sent in game, with a menu open, or while the player is already holding use themselves (the synthesized release would let go of it)
+    unsafe fn update_auto_advance(&mut self, delta: f32) {
+        let waiting = matches!(self.game_state, GameState::Intermission | GameState::Finale)
+            && doom_is_menu_active() == 0
+            && !self.keys_down.contains(&input::KEY_SPACE);
+
+        if !self.options.auto_advance_screens || !waiting {
+            self.auto_advance_timer = 0.0;
+            return;
+        }
+
+        self.auto_advance_timer += delta;
+        if self.auto_advance_timer >= self.options.auto_advance_delay {
+            self.auto_advance_timer = 0.0;
+            // these screens read use from the player's ticcmd rather than the key event, so it's held long enough for a tic to see it
+            self.key_queue.push(KeyEvent { key: input::KEY_SPACE, down: true }, 0);
+            self.key_queue.push(KeyEvent { key: input::KEY_SPACE, down: false }, AUTO_ADVANCE_HOLD_FRAMES);
+        }
+    }
+
+    /// Send a key event to DOOM, keeping track of which keys are down so they can all be let go at once
+    fn send_key(&mut self, event: KeyEvent) {
+        unsafe {
+            if event.down {
+                doom_key_down(event.key);
+                self.held_keys.insert(event.key);
+            }
+            else {
+                doom_key_up(event.key);
+                self.held_keys.remove(&event.key);
+            }
+        }
+    }
+
+    /// Release every key DOOM thinks is held, so nothing's left stuck down (still moving or firing) when play picks back up.
+    /// The controller's keys are forgotten too, so anything still held on it gets pressed again fresh on the next frame. <br/>
+    /// This happens whenever the game's paused for the player (disconnecting the controller or going idle) - dbsdk has no suspend signal to hook as well
+    fn release_all_keys(&mut self) {
+        if !self.held_keys.is_empty() {
+            db::log(format!("Releasing {} held keys", self.held_keys.len()).as_str());
+        }
+
+        for key in std::mem::take(&mut self.held_keys) {
+            unsafe { doom_key_up(key) };
+        }
+
+        self.keys_down.clear();
+        self.key_press_tics.clear();
+        self.held_button_keys.clear();
+        self.menu_repeat.clear();
+    }
+
+    /// Pause the game if it's running. The pause key toggles, so it's only sent if the game isn't paused already
+    fn pause_game(&mut self) {
+        self.release_all_keys();
+        if GameState::query() == GameState::Level && unsafe { doom_is_paused() } == 0 {
+            self.key_queue.push_tap(bindings::KEY_PAUSE, 0);
+        }
+    }
+
+    /// Whether audio should be playing, which it isn't with no controller connected or while the player's idle
+    fn audio_active(&self) -> bool {
+        self.controller_connected && !self.idle
+    }
+
+    /// Pause the game & audio once there's been no input for idle_timeout, & pick the audio back up (from now) on the next input. <br/>
+    /// Like reconnecting a controller, the game stays paused until the player unpauses it. A -playdemo demo is left to play out, since nobody's expected to be pressing anything
+    fn update_idle(&mut self, state: GamepadState, delta: f32) {
+        if self.options.idle_timeout <= 0.0 {
+            return;
+        }
+        if self.playing_demo {
+            self.idle_timer = 0.0;
+            return;
+        }
+
+        let threshold = self.bindings.stick_threshold.unsigned_abs();
+        let sticks = [state.left_stick_x, state.left_stick_y, state.right_stick_x, state.right_stick_y];
+        let active = !state.button_mask.is_none() || sticks.iter().any(|v| v.unsigned_abs() > threshold);
+
+        if active {
+            if self.idle {
+                db::log("Input received, no longer idle");
+                self.idle = false;
+                self.audio_schedule_time = audio::get_time();
+                self.apply_music_volume();
+            }
+            self.idle_timer = 0.0;
+            return;
+        }
+
+        self.idle_timer += delta;
+        if !self.idle && self.idle_timer >= self.options.idle_timeout {
+            db::log(format!("No input for {} seconds, pausing", self.options.idle_timeout).as_str());
+            self.idle = true;
+            self.pause_game();
+            self.apply_music_volume();
+        }
+    }
+
+    /// Pick audio back up from now rather than trying to catch up on everything missed while disconnected. The game stays paused until the player unpauses it
+    fn on_controller_reconnected(&mut self) {
+        db::log("Controller reconnected");
+        self.controller_connected = true;
+
+        self.audio_schedule_time = audio::get_time();
+        self.apply_music_volume();
+    }
+
+    fn draw_heap_stats(&mut self) {
+        if self.show_heap_stats {
+            let text = format!("HEAP {}K  PEAK {}K", heap::current_bytes() / 1024, heap::peak_bytes() / 1024);
+            self.overlay.draw_text(4.0, 4.0, 1.0, &text, Color32::new(255, 255, 0, 255));
+        }
+    }
+
+    /// List every file DOOM has open, below the memory readout. A handle which stays on this list after DOOM's done with the file has leaked
+    fn draw_open_files(&mut self) {
+        if !self.show_open_files {
+            return;
+        }
+
+        let open_files = files::open_files();
+        let mut y = 4.0 + overlay::LINE_HEIGHT;
+        self.overlay.draw_text(4.0, y, 1.0, &format!("OPEN FILES {}", open_files.len()), Color32::new(255, 255, 0, 255));
+
+        for (handle, path) in open_files {
+            y += overlay::LINE_HEIGHT;
+            self.overlay.draw_text(4.0, y, 1.0, &format!("{} {}", handle, path), Color32::new(255, 255, 0, 255));
+        }
+    }
+
+    fn draw_fps(&mut self) {
+        if !self.options.show_fps {
+            return;
         }
 
-        // we have a rotating buffer of audio samples we use to upload audio data
-        // NOTE: this will automatically deallocate the previous buffers here
+        let text = match self.frame_timer.frame_time() {
+            Some(frame_time) => format!("{:.1} FPS ({:.1}MS)", 1.0 / frame_time, frame_time * 1000.0),
+            None => "-- FPS".to_string(),
+        };
 
-        // this is a little tricky:
-        // basically, instead of queueing audio chunks right away, we actually stuff them into a buffer and wait
-        // then, when we get the next buffer, we actually take its first sample and append it to the start of the LAST buffer and submit that
-        // this is all to make DreamBox's 2-tap sampling play nicely - b/c at the end of one of our submitted samples, DreamBox doesn't take the next sample we queue up into account,
-        // so there's a single sample of aliasing in between every single buffer we submit and it ends up sounding scratchy
-        // this fixes that by basically making each buffer end with the next buffer's starting sample
+        let x = overlay::SCREEN_WIDTH - Overlay::text_width(&text, 1.0) - 4.0;
+        self.overlay.draw_text(x, 4.0, 1.0, &text, Color32::new(255, 255, 0, 255));
+    }
 
-        match &mut self.audio_queue[0] {
-            Some(v1) => {
-                // had a previous buffer, append the first sample of this new buffer to the end and queue that
-                v1.push(data_l[0]);
-                let newbuf_l = AudioSample::create_s16(v1, 11025).expect("Failed creating audio sample");
-                let handle_l = newbuf_l.handle;
-                self.audio_buf[0][self.next_buf % AUDIO_NUM_BUFFERS] = Some(newbuf_l);
-                MyApp::schedule_voice(handle_l, 0, -1.0, t);
-            }
-            None => {
-            }
+    fn draw_controller_disconnected(&mut self) {
+        if !self.controller_connected {
+            self.overlay.draw_panel(2.0, &[
+                ("CONTROLLER DISCONNECTED", Color32::new(255, 64, 64, 255)),
+                ("", Color32::new(0, 0, 0, 0)),
+                ("RECONNECT TO CONTINUE", Color32::new(255, 255, 255, 255)),
+            ]);
         }
+    }
 
-        match &mut self.audio_queue[1] {
-            Some(v2) => {
-                // had a previous buffer, append the first sample of this new buffer to the end and queue that
-                v2.push(data_r[0]);
-                let newbuf_r = AudioSample::create_s16(v2, 11025).expect("Failed creating audio sample");
-                let handle_r = newbuf_r.handle;
-                self.audio_buf[1][self.next_buf % AUDIO_NUM_BUFFERS] = Some(newbuf_r);
-                MyApp::schedule_voice(handle_r, 1, 1.0, t);
-            }
-            None => {
-            }
+    /// Load the next preloaded MIDI track & draw the loading screen <br/>
+    /// Returns true while preloading is still in progress
+    fn update_midi_preload(&mut self) -> bool {
+        let preload = match &mut self.midi_preload {
+            Some(v) => v,
+            None => return false,
+        };
+
+        let mut cache = MIDI_CACHE.write().unwrap();
+        preload.step(&mut cache);
+
+        if preload.is_done() {
+            db::log(format!("Preloaded {} bytes of MIDI data", cache.size()).as_str());
+            self.midi_preload = None;
+            return false;
         }
 
-        // replace audio in the queue with new chunk
-        self.audio_queue[0] = Some(data_l);
-        self.audio_queue[1] = Some(data_r);
+        let bar_width = overlay::SCREEN_WIDTH * 0.5;
+        let bar_x = (overlay::SCREEN_WIDTH - bar_width) * 0.5;
+        let bar_y = overlay::SCREEN_HEIGHT * 0.5;
 
-        self.next_buf += 1;
+        vdp::clear_color(Color32::new(0, 0, 0, 255));
+        self.overlay.draw_text_centered(bar_y - overlay::LINE_HEIGHT * 3.0, 2.0, "LOADING MUSIC", Color32::new(255, 255, 255, 255));
+        self.overlay.draw_rect(bar_x, bar_y, bar_width, 8.0, Color32::new(64, 64, 64, 255));
+        self.overlay.draw_rect(bar_x, bar_y, bar_width * preload.progress(), 8.0, Color32::new(255, 0, 0, 255));
+        self.overlay.flush();
+
+        true
     }
 
     pub fn update(&mut self) {
-        let delta = 1.0 / 60.0;
+        // frames are measured against the same clock DOOM is timed by, & can only be measured while it's running
+        let now = if USE_REAL_CLOCK.load(Ordering::Relaxed) { Some(audio::get_time()) } else { None };
+        let measured_delta = self.frame_timer.tick(now);
+        let delta = match measured_delta {
+            Some(measured) if self.options.measured_delta => measured,
+            _ => 1.0 / 60.0,
+        };
 
-        let gp = Gamepad::new(GamepadSlot::SlotA);
-        let new_state = gp.read_state();
-        let prev_state = self.prev_gp_state;
-        self.prev_gp_state = new_state;
+        // turning always goes by the real frame time when there is one (whatever measured_delta is set to), since DOOM's clock is real time too
+        // & a fixed step would turn slower whenever frames are dropped
+        let turn_delta = measured_delta.unwrap_or(1.0 / 60.0);
 
-        // we don't actually have a real clock, so we're going to just lie to DOOM about what time it is lol
-        self.time += delta;
-        unsafe {
-            TIME = self.time;
+        if self.update_midi_preload() {
+            return;
         }
 
-        if self.audio_schedule_time < audio::get_time() {
-            db::log(format!("Audio schedule time fell behind real time, recovering...").as_str());
-            self.audio_schedule_time = audio::get_time();
+        self.update_clock(delta);
+
+        // on the frames in between renders, whatever was last drawn stays on screen
+        let render = self.render_frame == 0;
+        self.render_frame = (self.render_frame + 1) % self.options.render_every_n;
+
+        // the controller is read as late as possible, right before DOOM runs, so there's as little delay as possible between reading it & DOOM acting on it
+        let (raw_state, prev_raw_state) = self.read_gamepads();
+        let (raw_state, prev_raw_state) = self.input_replay.apply(raw_state, prev_raw_state);
+        self.update_idle(raw_state, delta);
+
+        let new_state = if self.update_start_gate(raw_state) {
+            self.handle_ui_input(raw_state, prev_raw_state)
         }
+        else {
+            neutral_gp_state()
+        };
+
+        // from here on the sticks are strafe/forward & turn/look, whichever physical stick they're on
+        let new_state = self.bindings.stick_layout.apply(new_state);
 
-        // NOTE: DOOM audio is 11025 Hz, 512 samples * 2 channels per buffer
-        if audio::get_time() >= self.audio_schedule_time - AUDIO_LOOKAHEAD_TIME {
-            self.process_audio();
-            self.audio_schedule_time += 512.0 / 11025.0;
+        // a demo given with -playdemo plays through uninterrupted, so only the host's own combos work until it's over (DOOM then opens the menu)
+        let playing_demo = unsafe { doom_get_demo_playback() } == DEMO_PLAYBACK_SINGLE;
+        if playing_demo != self.playing_demo {
+            db::log(if playing_demo { "Demo playing, controller input held off" } else { "Demo finished, controller input back on" });
+            self.playing_demo = playing_demo;
         }
+        let new_state = if playing_demo { neutral_gp_state() } else { new_state };
 
         unsafe {
-            if new_state.is_pressed(gamepad::GamepadButton::R2) && !prev_state.is_pressed(gamepad::GamepadButton::R2) {
-                doom_key_down(0x80 + 0x1d);
+            // while the automap is open the right stick pans it instead of turning
+            let automap_mode = doom_get_automap_mode();
+            // turn keys from the right stick, in keyboard turn mode
+            let mut turn_keys = Vec::new();
+            if automap_mode != AUTOMAP_CLOSED {
+                // the automap only pans with follow mode off, so it's switched off the first time the stick is pushed.
+                // the pan keys wait until it's actually off, since with it on they'd move the player instead
+                if automap_mode == AUTOMAP_FOLLOWING {
+                    if self.bindings.automap_pan_pushed(&new_state) && !self.automap_follow_sent {
+                        self.key_queue.push_tap(bindings::KEY_AUTOMAP_FOLLOW, 0);
+                        self.automap_follow_sent = true;
+                    }
+                }
+                else {
+                    self.automap_follow_sent = false;
+                }
+
+                self.mx = 0.0;
+                self.my = 0.0;
             }
-            else if !new_state.is_pressed(GamepadButton::R2) && prev_state.is_pressed(GamepadButton::R2) {
-                doom_key_up(0x80 + 0x1d);
+            else {
+                self.automap_follow_sent = false;
+
+                let mouse_speed = turn_delta * MOUSE_SPEED * self.bindings.mouse_sensitivity;
+                let turn_dir = if self.bindings.invert_x { -1.0 } else { 1.0 };
+                let turn = match self.options.turn_mode {
+                    TurnMode::Mouse => {
+                        let turn_axis = input::response_curve(self.bindings.right_stick.axis(new_state.right_stick_x), self.bindings.response_curve);
+                        input::take_mouse_units(&mut self.mx, turn_axis * turn_dir * mouse_speed)
+                    }
+                    TurnMode::Keyboard => {
+                        // the stick only picks a direction, DOOM handles the speed (& its slower start) the same as for a held key.
+                        // in the menus the arrow keys would move the cursor instead, so nothing is pressed there
+                        self.mx = 0.0;
+                        let (left, right) = if self.bindings.invert_x { (StickDir::Right, StickDir::Left) } else { (StickDir::Left, StickDir::Right) };
+                        let pushed = |dir: StickDir| dir.is_pushed_on(new_state.right_stick_x, new_state.right_stick_y, self.bindings.stick_threshold, &self.bindings.right_stick);
+                        if doom_is_menu_active() == 0 {
+                            if pushed(left) {
+                                turn_keys.push(bindings::KEY_LEFT_ARROW);
+                            }
+                            else if pushed(right) {
+                                turn_keys.push(bindings::KEY_RIGHT_ARROW);
+                            }
+                        }
+                        0
+                    }
+                };
+
+                // DOOM has no vertical look - like a real mouse, vertical motion walks forwards & back (pushing up moves forwards)
+                let walk = if self.bindings.mouse_look_enabled {
+                    let look_exponent = if self.bindings.response_curve_look { self.bindings.response_curve } else { 1.0 };
+                    let look_axis = input::response_curve(self.bindings.right_stick.axis(new_state.right_stick_y), look_exponent);
+                    input::take_mouse_units(&mut self.my, -look_axis * mouse_speed)
+                }
+                else {
+                    0
+                };
+
+                doom_mouse_move(turn, walk);
             }
 
-            if new_state.is_pressed(gamepad::GamepadButton::L2) && !prev_state.is_pressed(gamepad::GamepadButton::L2) {
-                doom_key_down(0x80 + 0x36);
-            }
-            else if !new_state.is_pressed(GamepadButton::L2) && prev_state.is_pressed(GamepadButton::L2) {
-                doom_key_up(0x80 + 0x36);
-            }
-
-            if new_state.is_pressed(gamepad::GamepadButton::R1) && !prev_state.is_pressed(gamepad::GamepadButton::R1) {
-                doom_key_down(101);
-            }
-            else if !new_state.is_pressed(GamepadButton::R1) && prev_state.is_pressed(GamepadButton::R1) {
-                doom_key_up(101);
+            // work out every key the bindings want held this frame, then press & release whatever changed.
+            // several inputs can share a key (like the d-pad & stick), so it's only released once none of them want it
+            let ctx = MapContext {
+                always_run: self.options.always_run,
+                analog_move: self.options.analog_move,
+                automap_pan: automap_mode != AUTOMAP_CLOSED && automap_mode != AUTOMAP_FOLLOWING,
+            };
+            let (mut wanted, held_button_keys) = self.bindings.map_input(&new_state, &ctx, &self.held_button_keys, |keys| self.resolve_keys(keys));
+            self.held_button_keys = held_button_keys;
+
+            if !turn_keys.is_empty() {
+                wanted.extend(turn_keys);
+                wanted.sort_unstable();
+                wanted.dedup();
             }
 
-            if new_state.is_pressed(gamepad::GamepadButton::L1) && !prev_state.is_pressed(gamepad::GamepadButton::L1) {
-                doom_key_down(113);
-            }
-            else if !new_state.is_pressed(GamepadButton::L1) && prev_state.is_pressed(GamepadButton::L1) {
-                doom_key_up(113);
+            if self.options.analog_move {
+                let run = wanted.contains(&bindings::KEY_SHIFT);
+                let (forward, side) = input::analog_move(new_state.left_stick_x, new_state.left_stick_y, run, &self.bindings.left_stick);
+                doom_set_move(forward, side);
             }
 
-            if new_state.is_pressed(gamepad::GamepadButton::Start) && !prev_state.is_pressed(gamepad::GamepadButton::Start) {
-                doom_key_down(0xff);
-            }
-            else if !new_state.is_pressed(GamepadButton::Start) && prev_state.is_pressed(GamepadButton::Start) {
-                doom_key_up(0xff);
+            if self.options.rumble_enabled && wanted.contains(&bindings::KEY_CTRL) && !self.keys_down.contains(&bindings::KEY_CTRL) {
+                self.rumble.fire();
             }
 
-            if new_state.is_pressed(gamepad::GamepadButton::A) && !prev_state.is_pressed(gamepad::GamepadButton::A) {
-                doom_key_down(32);
-                doom_key_down(13);
-            }
-            else if !new_state.is_pressed(GamepadButton::A) && prev_state.is_pressed(GamepadButton::A) {
-                doom_key_up(32);
-                doom_key_up(13);
+            // DOOM only reads posted key events once per tic (& not at all during screen wipes), so a key released before it's read the press
+            // would go down & up in the same tic, & the press would never register. those keys are held over until DOOM has caught up
+            let input_tic = doom_get_input_tic();
+
+            let mut held_over = Vec::new();
+            for event in input::key_events(&self.keys_down, &wanted) {
+                if event.down {
+                    self.send_key(event);
+                    self.key_press_tics.insert(event.key, input_tic);
+                }
+                else if self.key_press_tics.get(&event.key) == Some(&input_tic) {
+                    held_over.push(event.key);
+                }
+                else {
+                    self.send_key(event);
+                    self.key_press_tics.remove(&event.key);
+                }
             }
 
-            if new_state.is_pressed(gamepad::GamepadButton::B) && !prev_state.is_pressed(gamepad::GamepadButton::B) {
-                doom_key_down(127);
-            }
-            else if !new_state.is_pressed(GamepadButton::B) && prev_state.is_pressed(GamepadButton::B) {
-                doom_key_up(127);
-            }
+            wanted.extend(held_over);
+            self.keys_down = wanted;
 
-            if new_state.is_pressed(gamepad::GamepadButton::X) && !prev_state.is_pressed(gamepad::GamepadButton::X) {
-                doom_key_down(27);
+            // DOOM's menus only move once per press, so held directions are pressed again on a timer. in game DOOM handles held keys itself
+            if doom_is_menu_active() != 0 {
+                let held: Vec<i32> = self.keys_down.iter().copied().filter(|v| MENU_REPEAT_KEYS.contains(v)).collect();
+                for key in self.menu_repeat.update(&held, delta, self.options.repeat_delay, self.options.repeat_rate) {
+                    self.send_key(KeyEvent { key, down: true });
+                }
             }
-            else if !new_state.is_pressed(GamepadButton::X) && prev_state.is_pressed(GamepadButton::X) {
-                doom_key_up(27);
+            else {
+                self.menu_repeat.clear();
             }
 
-            if new_state.is_pressed(gamepad::GamepadButton::Select) && !prev_state.is_pressed(gamepad::GamepadButton::Select) {
-                doom_key_down(9);
-            }
-            else if !new_state.is_pressed(GamepadButton::Select) && prev_state.is_pressed(GamepadButton::Select) {
-                doom_key_up(9);
+            self.update_auto_advance(delta);
+            let mut due = Vec::new();
+            self.key_queue.dispatch(|event| due.push(event));
+            due.extend(INJECTED_KEYS.lock().unwrap_or_else(|e| e.into_inner()).drain(..));
+            for event in due {
+                self.send_key(event);
             }
+            self.restore_quicksave_slot();
 
-            let prev_mx = self.mx as i32;
-            self.mx += (new_state.right_stick_x as f32 / 32767.0) * delta * 4096.0;
-            let new_mx = self.mx as i32;
-
-            doom_mouse_move(new_mx - prev_mx, 0);
-
-            let new_left = new_state.left_stick_x < -1024 || new_state.is_pressed(GamepadButton::Left);
-            let new_right = new_state.left_stick_x > 1024 || new_state.is_pressed(GamepadButton::Right);
+            let stage_start = audio::get_time();
+            doom_update();
+            benchmark::record(Stage::Update, stage_start);
 
-            let new_up = new_state.left_stick_y > 1024 || new_state.is_pressed(GamepadButton::Up);
-            let new_down = new_state.left_stick_y < -1024 || new_state.is_pressed(GamepadButton::Down);
+            self.log_hud_message();
 
-            if new_left && !self.prev_left {
-                doom_key_down(44);
-            }
-            else if !new_left && self.prev_left {
-                doom_key_up(44);
+            // every level's damage flash is measured against how red it usually is, so a new one means calibrating again
+            let level_count = doom_get_level_count();
+            if level_count != self.level_count {
+                self.level_count = level_count;
+                self.rumble.recalibrate();
             }
 
-            if new_right && !self.prev_right {
-                doom_key_down(46);
-            }
-            else if !new_right && self.prev_right {
-                doom_key_up(46);
+            // update screen texture (if there's no framebuffer yet, the last frame just stays on screen)
+            let stage_start = audio::get_time();
+            let fb = if render { doom_framebuffer() } else { None };
+            if let Some(fb_data_slice) = fb {
+                if self.options.rumble_enabled {
+                    self.rumble.sample_frame(fb_data_slice, self.screen_size.0 as usize, self.screen_size.1 as usize, self.options.rumble_threshold);
+                }
+
+                let (width, height) = self.screen_size;
+                let update_rect = Rectangle::new(0, 0, width, height);
+
+                let back_buf = 1 - self.front_buf;
+                let upload = if self.postprocess_frame(fb_data_slice) {
+                    &self.fb_scratch[..(width * height) as usize * self.options.canvas_format.bytes_per_pixel()]
+                }
+                else {
+                    fb_data_slice
+                };
+                self.canvas_tex[back_buf].set_texture_data_region(0, Some(update_rect), upload);
+                self.front_buf = back_buf;
+                benchmark::record(Stage::Upload, stage_start);
             }
+        }
 
-            if new_up && !self.prev_up {
-                doom_key_down(0xad);
+        // audio comes after DOOM has run, so it isn't holding up the input above (& any sounds DOOM just started go out in this buffer)
+        // nothing new is scheduled while the controller is disconnected (or the player's idle), so whatever was already queued just plays out
+        if self.audio_active() {
+            // the schedule starts out behind, which just means nothing has been scheduled yet
+            if self.audio_schedule_time < 0.0 {
+                self.audio_schedule_time = audio::get_time();
             }
-            else if !new_up && self.prev_up {
-                doom_key_up(0xad);
+            else if self.audio_schedule_time < audio::get_time() {
+                self.resync_audio();
             }
-
-            if new_down && !self.prev_down {
-                doom_key_down(0xaf);
-            }
-            else if !new_down && self.prev_down {
-                doom_key_up(0xaf);
+            self.track_audio_underruns();
+
+            // NOTE: DOOM audio is 11025 Hz, with each buffer holding however many samples * 2 channels its mixer produces (512 in stock DOOM)
+            // if frames are taking longer than a buffer lasts, several buffers get processed at once so the audio keeps up
+            for _ in 0..AUDIO_MAX_BUFFERS_PER_FRAME {
+                if audio::get_time() < self.audio_schedule_time - self.audio_lookahead() {
+                    break;
+                }
+
+                let stage_start = audio::get_time();
+                let sample_cnt = self.process_audio();
+                self.audio_schedule_time += sample_cnt as f64 / resample::DOOM_SAMPLERATE as f64;
+                benchmark::record(Stage::Audio, stage_start);
             }
+        }
 
-            self.prev_left = new_left;
-            self.prev_right = new_right;
-            self.prev_up = new_up;
-            self.prev_down = new_down;
-
-            doom_update();
+        self.update_reverb();
 
-            // update screen texture
-            let fb_data = doom_get_framebuffer(4) as *const u8;
-            let fb_data_slice = std::slice::from_raw_parts(fb_data, 320 * 200 * 4);
+        if self.options.duck_music {
+            self.music_ducker.update(delta, self.options.duck_amount, self.options.duck_release);
+            self.apply_music_volume();
+        }
 
-            let update_rect = Rectangle::new(0, 0, 320, 200);
+        music::release_stopped_streams();
 
-            self.canvas_tex.set_texture_data_region(0, Some(update_rect), fb_data_slice);
+        let game_state = GameState::query();
+        if game_state != self.game_state {
+            self.on_game_state_changed(self.game_state, game_state);
+            self.game_state = game_state;
         }
 
-        vdp::clear_color(Color32::new(0, 0, 0, 255));
+        self.update_rumble(delta);
 
-        let vertex_data = [
-            PackedVertex::new(Vector4::new(-1.0, -1.0, 0.0, 1.0), Vector2::new(0.0, 0.78125), Color32::new(255, 255, 255, 255), Color32::new(0, 0, 0, 0)),
-            PackedVertex::new(Vector4::new(1.0, -1.0, 0.0, 1.0), Vector2::new(0.625, 0.78125), Color32::new(255, 255, 255, 255), Color32::new(0, 0, 0, 0)),
-            PackedVertex::new(Vector4::new(-1.0, 1.0, 0.0, 1.0), Vector2::new(0.0, 0.0), Color32::new(255, 255, 255, 255), Color32::new(0, 0, 0, 0)),
+        if render {
+            vdp::clear_color(Color32::new(0, 0, 0, 255));
 
-            PackedVertex::new(Vector4::new(-1.0, 1.0, 0.0, 1.0), Vector2::new(0.0, 0.0), Color32::new(255, 255, 255, 255), Color32::new(0, 0, 0, 0)),
-            PackedVertex::new(Vector4::new(1.0, -1.0, 0.0, 1.0), Vector2::new(0.625, 0.78125), Color32::new(255, 255, 255, 255), Color32::new(0, 0, 0, 0)),
-            PackedVertex::new(Vector4::new(1.0, 1.0, 0.0, 1.0), Vector2::new(0.625, 0.0), Color32::new(255, 255, 255, 255), Color32::new(0, 0, 0, 0))
-        ];
-        {
-            vdp::bind_texture(Some(&self.canvas_tex));
+            let max_uv = (self.screen_size.0 as f32 / self.canvas_size.0 as f32, self.screen_size.1 as f32 / self.canvas_size.1 as f32);
+            let vertex_data = MyApp::fullscreen_quad(&self.options, max_uv);
+            {
+                vdp::bind_texture(Some(&self.canvas_tex[self.front_buf]));
+            }
+            vdp::draw_geometry_packed(vdp::Topology::TriangleList, &vertex_data);
         }
-        vdp::draw_geometry_packed(vdp::Topology::TriangleList, &vertex_data);
+
+        self.toasts.update(delta);
+        self.toasts.draw(&mut self.overlay);
+        self.settings_hud.update(delta);
+        self.settings_hud.draw(&mut self.overlay);
+
+        self.draw_start_gate();
+        self.keyboard.draw(&mut self.overlay);
+        self.draw_latency_calibration();
+        self.draw_confirmation();
+        self.draw_load_error(delta);
+        self.draw_heap_stats();
+        self.draw_open_files();
+        self.draw_fps();
+        self.draw_controller_disconnected();
+        self.overlay.flush();
     }
 }
 
+// frame-counted time, which DOOM is given if the real clock isn't available
 static mut TIME: f32 = 0.0;
 
+// DOOM's clock normally follows the audio timer (the only monotonic clock dbsdk has), counted from when the app started
+static USE_REAL_CLOCK: AtomicBool = AtomicBool::new(false);
+static mut CLOCK_START: f64 = 0.0;
+
+/// Seconds since the app started, going by the real clock
+fn real_clock_time() -> f64 {
+    unsafe { audio::get_time() - CLOCK_START }
+}
+
+// the game speed, & both clocks' readings as of the last time it changed
+static mut TIME_SCALE: f64 = 1.0;
+static mut CLOCK_ANCHOR_REAL: f64 = 0.0;
+static mut CLOCK_ANCHOR_GAME: f64 = 0.0;
+
+/// The real clock, sped up or slowed down by the game speed
+fn game_clock_time() -> f64 {
+    unsafe { CLOCK_ANCHOR_GAME + (real_clock_time() - CLOCK_ANCHOR_REAL) * TIME_SCALE }
+}
+
+// set by doom_exit when the player quits - the app finishes the current frame, then switches over to shutting down
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+// code DOOM exited with, kept around for the host
+static EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
+// set by doom_exit when DOOM hits a fatal error - every frame from then on just shows the error
+static DOOM_FAILED: AtomicBool = AtomicBool::new(false);
+
+// set once the loading screen has been up for a frame, after which the app gets created
+static LOADING_SCREEN_SHOWN: AtomicBool = AtomicBool::new(false);
+
 lazy_static! {
     static ref MY_APP: RwLock<MyApp> = RwLock::new(MyApp::new());
+    static ref MIDI_CACHE: RwLock<MidiCache> = RwLock::new(MidiCache::new());
+    // key events from inject_key, waiting for the next update
+    static ref INJECTED_KEYS: Mutex<Vec<KeyEvent>> = Mutex::new(Vec::new());
+    // draws the error screen once DOOM has failed, since the app's own overlay can't be reached by then
+    static ref FATAL_OVERLAY: Mutex<Option<Overlay>> = Mutex::new(None);
+}
+
+/// Send a DOOM key press (or release) from outside the gamepad path, for automation & accessibility tools. `keycode` is a doom_key_t value. <br/>
+/// Events are delivered in order on the next update, right before doom_update, & tracked in held_keys along with the gamepad's keys -
+/// so a key pressed this way stays down until it's released this way, or until the game's paused for the player & release_all_keys lets go of everything. <br/>
+/// This is safe to call from any thread, & from inside a frame: it only takes its own lock, never the app's (which tick holds for the whole frame)
+pub fn inject_key(keycode: i32, down: bool) {
+    INJECTED_KEYS.lock().unwrap_or_else(|e| e.into_inner()).push(KeyEvent { key: keycode, down });
 }
 
 extern {
@@ -348,7 +2302,29 @@ extern {
     fn doom_mouse_move(dx: i32, dy: i32);
 
     fn doom_get_framebuffer(channels: i32) -> *const c_void;
+    fn doom_get_framebuffer_size(width: *mut i32, height: *mut i32);
     fn doom_get_sound_buffer() -> *const i16;
+    fn doom_get_sound_buffer_size() -> i32;
+    fn doom_get_sound_samplerate() -> i32;
+
+    fn doom_get_gamestate() -> i32;
+    fn doom_get_tic() -> i32;
+    fn doom_get_level_count() -> i32;
+    fn doom_is_menu_active() -> i32;
+    fn doom_is_paused() -> i32;
+    fn doom_get_demo_playback() -> i32;
+    fn doom_get_automap_mode() -> i32;
+    fn doom_get_input_tic() -> i32;
+    fn doom_get_message() -> *const c_char;
+    fn doom_get_message_count() -> i32;
+    fn doom_get_weapon_cycle_key(next: i32) -> i32;
+    fn doom_set_quicksave_slot(slot: i32, description: *const c_char);
+    fn doom_get_quicksave_slot() -> i32;
+    fn doom_get_music_name(musicnum: i32) -> *const c_char;
+    fn doom_start_sound(sfx_id: i32);
+    fn doom_set_attract_mode(enabled: i32);
+    fn doom_set_wipes(enabled: i32);
+    fn doom_set_move(forward: i32, side: i32);
 }
 
 extern {
@@ -361,37 +2337,230 @@ extern {
     fn fs_eof(handle: i32) -> bool;
 }
 
+/// How many previous buffers of audio have to be kept alive on each voice, so none are deallocated before they've played. <br/>
+/// technically sounds will be buffered up to (lookahead * 2) seconds in advance - at the default lookahead of 0.05s + options::MAX_LATENCY_COMP = 0.15s,
+/// w/ a buffer size of 512 samples @ 11025 Hz, this is enough time to contain just under 7 buffers worth of audio (0.15 / (512.0/11025.0)) * 2 = 6.4599609375.
+/// a frame catching up can queue up to AUDIO_MAX_BUFFERS_PER_FRAME at once, the last of which may land just past that window,
+/// so we round up & allow for the extra in-flight buffers
+fn audio_buffer_count(lookahead: f64, samples_per_buffer: usize) -> usize {
+    let buffer_time = samples_per_buffer as f64 / resample::DOOM_SAMPLERATE as f64;
+    let window = (lookahead + options::MAX_LATENCY_COMP) * 2.0;
+    ((window / buffer_time).ceil() as usize + AUDIO_MAX_BUFFERS_PER_FRAME - 1).max(AUDIO_MIN_BUFFERS)
+}
+
+/// Read a soundfont & start the synth with it. <br/>
+/// On failure, returns the reason along with a short summary for the screen - a soundfont which is there but corrupt or an unsupported variant is reported separately from one that's missing
+fn load_soundfont(path: &str) -> Result<(), (String, &'static str)> {
+    match files::read_file_fully(path) {
+        Ok(sf_buf) => audio::init_synth(&sf_buf).map_err(|e| (format!("{} isn't a usable soundfont ({:?})", path, e), "BAD SOUNDFONT")),
+        Err(e) => Err((e.to_string(), "NO SOUNDFONT")),
+    }
+}
+
+/// DOOM's RGBA framebuffer, or None if it doesn't have one (which is logged the first time) <br/>
+/// The slice points straight into DOOM's memory, so it's only valid until the next doom_update <br/>
+/// The palette effects (red for pain & berserk, gold for pickups, green for the radiation suit) are already in here - DOOM switches between
+/// PLAYPAL's tinted palettes for them (ST_doPaletteStuff), & doom_get_framebuffer expands the indexed screen through whichever one is current.
+/// Only the 1 channel (indexed) framebuffer comes without them
+fn doom_framebuffer() -> Option<&'static [u8]> {
+    static NULL_LOGGED: AtomicBool = AtomicBool::new(false);
+
+    let fb = unsafe { doom_get_framebuffer(DOOM_FB_CHANNELS) } as *const u8;
+    if fb.is_null() {
+        if !NULL_LOGGED.swap(true, Ordering::Relaxed) {
+            db::log("DOOM returned a null framebuffer, skipping the screen update");
+        }
+        return None;
+    }
+
+    Some(unsafe { std::slice::from_raw_parts(fb, doom_fb_size()) })
+}
+
+/// Size of DOOM's framebuffer in pixels, as DOOM reports it (rather than assuming 320x200)
+fn doom_screen_size() -> (i32, i32) {
+    let (mut width, mut height) = (0, 0);
+    unsafe { doom_get_framebuffer_size(&mut width, &mut height) };
+    (width, height)
+}
+
+/// Bytes in DOOM's RGBA framebuffer
+fn doom_fb_size() -> usize {
+    let (width, height) = doom_screen_size();
+    (width * height * DOOM_FB_CHANNELS) as usize
+}
+
+/// Keys a button bound to the given keys should press, depending on whether DOOM's menu is open. <br/>
+/// In context mode, a button bound to both use & menu select only presses whichever one applies (use everywhere outside the menu, automap included)
+fn resolve_keys_for(keys: &[i32], a_button_mode: AButtonMode, menu_active: bool) -> Vec<i32> {
+    let dual = keys.contains(&input::KEY_SPACE) && keys.contains(&input::KEY_ENTER);
+
+    if a_button_mode == AButtonMode::Context && dual {
+        let skip = if menu_active { input::KEY_SPACE } else { input::KEY_ENTER };
+        keys.iter().copied().filter(|v| *v != skip).map(resolve_action).collect()
+    }
+    else {
+        keys.iter().copied().map(resolve_action).collect()
+    }
+}
+
+/// Swap the weapon cycling stand-ins for whichever keys DOOM has them bound to
+fn resolve_action(key: i32) -> i32 {
+    match key {
+        bindings::ACTION_PREV_WEAPON => unsafe { doom_get_weapon_cycle_key(0) },
+        bindings::ACTION_NEXT_WEAPON => unsafe { doom_get_weapon_cycle_key(1) },
+        _ => key,
+    }
+}
+
+/// Check whether the given savegame slot has a save in it, going through the same file IO path DOOM uses
+fn save_slot_occupied(slot: i32) -> bool {
+    let filename = format!("doomsav{}.dsg\0", slot);
+    unsafe {
+        let handle = doom_open(filename.as_ptr() as *const c_char, b"rb\0".as_ptr() as *const c_char);
+        if handle == 0 {
+            return false;
+        }
+        doom_close(handle);
+    }
+    true
+}
+
+/// Names of every music track DOOM knows about, as passed to doom_playmus
+fn music_names() -> Vec<String> {
+    let mut names = Vec::new();
+    let mut musicnum = 1;
+
+    loop {
+        let name = unsafe { doom_get_music_name(musicnum) };
+        if name.is_null() {
+            break;
+        }
+
+        names.push(unsafe { CStr::from_ptr(name) }.to_str().unwrap().to_string());
+        musicnum += 1;
+    }
+
+    names
+}
+
+/// Put up a loading message with nothing else on screen, for the frame before the app is created
+fn draw_loading_screen() {
+    // this overlay only lasts the one frame - the app builds its own
+    let mut overlay = Overlay::new();
+
+    vdp::clear_color(Color32::new(0, 0, 0, 255));
+    overlay.draw_text_centered(overlay::SCREEN_HEIGHT * 0.5 - overlay::LINE_HEIGHT, 2.0, "LOADING...", Color32::new(255, 255, 255, 255));
+    overlay.flush();
+}
+
+/// Clear the screen & show an error panel in place of the game
+fn draw_error_screen(overlay: &mut Overlay, error: &str, hint: &str) {
+    vdp::clear_color(Color32::new(0, 0, 0, 255));
+    overlay.draw_panel(2.0, &[
+        (error, Color32::new(255, 64, 64, 255)),
+        ("", Color32::new(0, 0, 0, 0)),
+        (hint, Color32::new(255, 255, 255, 255)),
+    ]);
+    overlay.flush();
+}
+
 fn tick() {
+    // creating the app loads the soundfont & WADs & runs doom_init, which takes long enough to notice. so the very first frame only shows
+    // a loading screen, & the app is created on the next one (with the loading screen still up while it blocks)
+    if !LOADING_SCREEN_SHOWN.swap(true, Ordering::Relaxed) {
+        draw_loading_screen();
+        return;
+    }
+
+    // once DOOM has hit a fatal error nothing updates any more, & the error screen stays up. the frame it happened in was abandoned with a trap (see doom_exit),
+    // so this is only reached if the host keeps calling the vsync callback after one. that frame never let go of the app (or never finished creating it,
+    // if it was doom_init that failed) - so this doesn't go anywhere near it, & has an overlay of its own
+    if DOOM_FAILED.load(Ordering::Relaxed) {
+        let mut overlay = FATAL_OVERLAY.lock().unwrap();
+        let overlay = overlay.get_or_insert_with(Overlay::new);
+        let error = format!("DOOM EXITED WITH ERROR CODE {}", EXIT_CODE.load(Ordering::Relaxed));
+        draw_error_screen(overlay, &error, "SEE THE LOG FOR DETAILS");
+        return;
+    }
+
     let mut my_app = MY_APP.write().unwrap();
-    my_app.update();
+
+    if SHUTDOWN.load(Ordering::Relaxed) {
+        my_app.update_shutdown();
+    }
+    else if my_app.fatal_error.is_some() {
+        my_app.draw_fatal_error();
+    }
+    else {
+        my_app.update();
+    }
 }
 
 unsafe extern "C" fn doom_playmus(id: *const c_char, looping: i32) {
     let mus_id = CStr::from_ptr(id).to_str().unwrap();
-    let path = format!("/cd/content/midi/{}.mid", mus_id);
-    db::log(format!("PLAY MUSIC: {}", path).as_str());
 
-    let mut midi_file = match FileStream::open(path.as_str(), FileMode::Read) {
-        Ok(v) => v,
-        _ => {
-            audio::set_midi_volume(0.0);
-            return;
+    // DOOM restarts the level music when the player dies, which would otherwise start the track over (or re-silence a missing one)
+    if music::is_current_track(mus_id) {
+        return;
+    }
+
+    let current_track = if looping != 0 { Some(mus_id) } else { None };
+
+    // a recording of the track takes priority over the MIDI, & doesn't need the synth
+    if music::play_streamed(mus_id, looping != 0) {
+        db::log(format!("PLAY MUSIC: {}", music::streamed_path(mus_id)).as_str());
+        audio::set_midi_volume(0.0);
+        music::set_music_playing(true);
+        music::set_current_track(current_track);
+        return;
+    }
+
+    music::stop_streamed();
+
+    if !music::music_available() {
+        music::set_music_playing(false);
+        return;
+    }
+
+    db::log(format!("PLAY MUSIC: {}", music::midi_path(mus_id)).as_str());
+
+    // use the preloaded copy if there is one, otherwise stream it from disc
+    let cache = MIDI_CACHE.read().unwrap();
+    let loaded;
+    let midi_buf = match cache.get(mus_id) {
+        Some(v) => v,
+        None => match music::read_midi(mus_id) {
+            Some(v) => {
+                loaded = v;
+                loaded.as_slice()
+            }
+            None => {
+                // dbsdk has no way to stop MIDI playback, so the previous track is silenced instead
+                music::warn_missing_track(mus_id);
+                music::set_music_playing(false);
+                music::set_current_track(current_track);
+                audio::set_midi_volume(0.0);
+                return;
+            }
         }
     };
 
-    midi_file.seek(std::io::SeekFrom::End(0)).unwrap();
-    let size = midi_file.position();
-    midi_file.seek(std::io::SeekFrom::Start(0)).unwrap();
-    let mut midi_buf: Vec<u8> = vec![0;size as usize];
-    midi_file.read_exact(&mut midi_buf).unwrap();
+    if audio::play_midi(midi_buf, looping != 0).is_err() {
+        db::log(format!("Failed playing {}, it may not be a valid MIDI file", music::midi_path(mus_id)).as_str());
+        music::set_music_playing(false);
+        music::set_current_track(None);
+        audio::set_midi_volume(0.0);
+        return;
+    }
 
-    audio::set_midi_volume(0.2);
-    audio::play_midi(&midi_buf, looping != 0).unwrap();
+    music::set_music_playing(true);
+    music::set_current_track(current_track);
+    audio::set_midi_volume(music::track_volume());
 }
 
 unsafe extern "C" fn doom_print(str: *const c_char) {
     let c_str = CStr::from_ptr(str);
-    db::log(c_str.to_str().unwrap());
+    log::print(&c_str.to_string_lossy());
 }
 
 unsafe extern "C" fn doom_malloc(size: i32) -> *mut c_void {
@@ -404,12 +2573,17 @@ unsafe extern "C" fn doom_malloc(size: i32) -> *mut c_void {
 
     // NOTE: removed write_unaligned b/c it is no longer necessary - malloc is already 8-byte aligned
 
+    // a negative size is treated as 0, and it's the clamped size which goes in the preamble so doom_free releases (and un-counts) the same amount
+    let size = size.max(0);
     let actual_size = 8 + usize::try_from(size).unwrap();
     let layout = Layout::array::<u8>(actual_size).unwrap().align_to(8).unwrap();
     let mem = unsafe { std::alloc::alloc(layout) };
-    if !mem.is_null() {
-        unsafe { mem.cast::<i64>().write(size.into()) };
+    if mem.is_null() {
+        return ptr::null_mut();
     }
+
+    unsafe { mem.cast::<i64>().write(size.into()) };
+    heap::record_alloc(actual_size);
     unsafe { mem.add(8) }.cast()
 }
 
@@ -418,40 +2592,115 @@ unsafe extern "C" fn doom_free(ptr: *mut c_void) {
 
     // NOTE: removed read_unaligned b/c it is no longer necessary - malloc is already 8-byte aligned
 
+    // same as C's free, freeing null does nothing
+    if ptr.is_null() {
+        return;
+    }
+
     let ptr = unsafe { ptr.sub(8) }.cast::<u8>();
     let size = unsafe { ptr.cast::<i64>().read() };
     let actual_size = 8 + usize::try_from(size).unwrap();
     let layout = Layout::array::<u8>(actual_size).unwrap().align_to(8).unwrap();
     unsafe { std::alloc::dealloc(ptr, layout) };
+    heap::record_free(actual_size);
 }
 
 unsafe extern "C" fn doom_open(filename: *const c_char, mode: *const c_char) -> i32 {
-    let mode_str = CStr::from_ptr(mode).to_str().unwrap();
-
-    let filemode = match mode_str {
-        "r" => FileMode::Read,
-        "rb" => FileMode::Read,
-        "w" => FileMode::Write,
-        "wb" => FileMode::Write,
-        _ => panic!("Unexpected file mode ({})", mode_str)
+    let handle = open_file(filename, mode);
+    if handle != 0 {
+        files::track_open(handle, &CStr::from_ptr(filename).to_string_lossy());
+    }
+    handle
+}
+
+unsafe fn open_file(filename: *const c_char, mode: *const c_char) -> i32 {
+    let mode_str = CStr::from_ptr(mode).to_string_lossy();
+
+    // DOOM opens savegames by bare file name, which are kept in HOME. demos are too, but they can also come off the disc
+    let filename_str = CStr::from_ptr(filename).to_string_lossy();
+    let is_demo = !filename_str.starts_with('/') && filename_str.to_ascii_lowercase().ends_with(".lmp");
+    let path = if is_demo && mode_str.starts_with('r') { demo_path(&filename_str) } else { save_path(&filename_str) };
+
+    // 0 is how a failed open is reported back to DOOM (same as fs_open), which it handles like any missing or unwritable file
+    let mode = match OpenMode::parse(&mode_str) {
+        Some(v) => v,
+        None => {
+            db::log(format!("Can't open {}: unsupported file mode '{}'", path, mode_str).as_str());
+            return 0;
+        }
+    };
+
+    if memfs::is_active() && path.strip_prefix(SAVE_DIR).is_some_and(|v| v.starts_with('/')) {
+        return memfs::open(&path, mode);
+    }
+
+    // WADs etc are looked up in every content root, since DOOM only knows about the first one (DOOMWADDIR)
+    let path = match path.strip_prefix(boot::CONTENT_DIR).and_then(|v| v.strip_prefix('/')) {
+        Some(relative) if mode == OpenMode::Read => files::find_content(relative),
+        _ => path,
     };
 
-    return fs_open(filename, filemode);
+    let path_cstr = CString::new(path.as_str()).unwrap();
+    match mode {
+        OpenMode::Read => fs_open(path_cstr.as_ptr(), FileMode::Read),
+        OpenMode::Write => fs_open(path_cstr.as_ptr(), FileMode::Write),
+        OpenMode::Append => {
+            // dbsdk has no append mode, so whatever's already in the file is written back out first, leaving the handle at the end
+            let existing = match files::read_file_fully(&path) {
+                Ok(v) => v,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => {
+                    db::log(format!("Can't append to {}: {}", path, e).as_str());
+                    return 0;
+                }
+            };
+
+            let handle = fs_open(path_cstr.as_ptr(), FileMode::Write);
+            if handle != 0 && !existing.is_empty() {
+                fs_write(handle, existing.as_ptr() as *const c_void, existing.len() as i32);
+            }
+            handle
+        }
+    }
 }
 
 unsafe extern "C" fn doom_close(handle: i32) {
+    files::track_close(handle);
+
+    if memfs::is_mem_handle(handle) {
+        return memfs::close(handle);
+    }
+
     fs_close(handle);
 }
 
 unsafe extern "C" fn doom_read(handle: i32, buf: *mut c_void, count: i32) -> i32 {
+    if memfs::is_mem_handle(handle) {
+        return memfs::read(handle, std::slice::from_raw_parts_mut(buf as *mut u8, count.max(0) as usize));
+    }
+
     return fs_read(handle, buf, count);
 }
 
 unsafe extern "C" fn doom_write(handle: i32, buf: *const c_void, count: i32) -> i32 {
+    if memfs::is_mem_handle(handle) {
+        return memfs::write(handle, std::slice::from_raw_parts(buf as *const u8, count.max(0) as usize));
+    }
+
     return fs_write(handle, buf, count);
 }
 
 unsafe extern "C" fn doom_seek(handle: i32, offset: i32, origin: i32) -> i32 {
+    if memfs::is_mem_handle(handle) {
+        let whence = match origin {
+            0 => memfs::Whence::Begin,
+            1 => memfs::Whence::Current,
+            2 => memfs::Whence::End,
+            _ => panic!("Unexpected seek origin"),
+        };
+        return memfs::seek(handle, offset, whence);
+    }
+
     match origin {
         0 => {
             return fs_seek(handle, offset, io::SeekOrigin::Begin);
@@ -469,20 +2718,40 @@ unsafe extern "C" fn doom_seek(handle: i32, offset: i32, origin: i32) -> i32 {
 }
 
 unsafe extern "C" fn doom_tell(handle: i32) -> i32 {
+    if memfs::is_mem_handle(handle) {
+        return memfs::tell(handle);
+    }
+
     return fs_tell(handle);
 }
 
 unsafe extern "C" fn doom_eof(handle: i32) -> i32 {
-    return if fs_eof(handle) { 1 } else { 0 };
+    let eof = if memfs::is_mem_handle(handle) { memfs::eof(handle) } else { fs_eof(handle) };
+    return if eof { 1 } else { 0 };
 }
 
 unsafe extern "C" fn doom_exit(code: i32) {
-    panic!("TODO: exit - code: {}", code);
+    EXIT_CODE.store(code, Ordering::Relaxed);
+    log::flush();
+
+    // quitting from the menu (I_Quit) is safe to return from, since DOOM has already shut everything down by then & just unwinds back out of doom_update
+    if code == 0 {
+        SHUTDOWN.store(true, Ordering::Relaxed);
+        return;
+    }
+
+    // NOTE: errors are a different story - I_Error treats this as noreturn (it's called right after printing the error), but wasm32 has no way to unwind or longjmp back out of doom_update,
+    // and DOOM's static state can't be reset for a second doom_init call (the WAD directory would just get appended to, etc). So there's nothing to recover to here.
+    // returning would run the rest of whatever DOOM was doing on the state that just failed, so the frame is abandoned with a panic instead.
+    // that traps (wasm32 panics abort), so the error code only makes it on screen (in tick) if the host carries on calling the vsync callback afterwards
+    DOOM_FAILED.store(true, Ordering::Relaxed);
+    panic!("DOOM exited with a fatal error (code {}) - see the log above for the error message", code);
 }
 
 unsafe extern "C" fn doom_gettime(sec: *mut i32, usec: *mut i32) {
-    let total_sec = TIME.floor();
-    let sec_rem = TIME - total_sec;
+    let time = if USE_REAL_CLOCK.load(Ordering::Relaxed) { game_clock_time() } else { TIME as f64 };
+    let total_sec = time.floor();
+    let sec_rem = time - total_sec;
 
     *sec = total_sec as i32;
     *usec = (sec_rem * 1000000.0) as i32;
@@ -495,7 +2764,7 @@ unsafe extern "C" fn doom_getenv(var: *const c_char) -> *const c_char {
             return b"/cd/content\0".as_ptr() as *const c_char;
         },
         "HOME" => {
-            return b"/ma\0".as_ptr() as *const c_char;
+            return SAVE_DIR_CSTR.as_ptr() as *const c_char;
         },
         _ => {
             return ptr::null();
@@ -503,9 +2772,346 @@ unsafe extern "C" fn doom_getenv(var: *const c_char) -> *const c_char {
     };
 }
 
+// host test builds get their own entry point
+#[cfg(not(test))]
 #[no_mangle]
 pub fn main(_: i32, _: i32) -> i32 {
     db::register_panic();
-    vdp::set_vsync_handler(Some(tick));
+
+    // benchmark mode runs its frames straight through instead of once per vsync, then logs the results
+    match BenchmarkConfig::load(benchmark::BENCHMARK_CONFIG_PATH) {
+        Some(config) => {
+            db::log(format!("Running benchmark for {} frames", config.frames).as_str());
+            benchmark::start();
+
+            for _ in 0..config.frames {
+                let frame_start = audio::get_time();
+                tick();
+                benchmark::record(Stage::Frame, frame_start);
+            }
+
+            benchmark::log_results();
+        }
+        None => vdp::set_vsync_handler(Some(tick)),
+    }
+
     return 0;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_run(tracker: &mut AllocTracker, alloc_ok: bool, count: u32) -> Vec<AllocChange> {
+        (0..count).map(|_| tracker.record(alloc_ok, true)).filter(|change| *change != AllocChange::None).collect()
+    }
+
+    #[test]
+    fn repeated_alloc_failures_fall_back_to_mono() {
+        let mut tracker = AllocTracker::new();
+        assert_eq!(tracker.record(false, true), AllocChange::Failing);
+        assert_eq!(tracker.record(false, true), AllocChange::None);
+        assert!(!tracker.forced_mono);
+        assert_eq!(tracker.record(false, true), AllocChange::FallBackToMono);
+        assert!(tracker.forced_mono);
+
+        // failing on in mono only logs again, there's nothing further to fall back to
+        assert_eq!(record_run(&mut tracker, false, 10), vec![AllocChange::Failing]);
+        assert!(tracker.forced_mono);
+    }
+
+    #[test]
+    fn alloc_failures_without_mono_fallback_stay_stereo() {
+        let mut tracker = AllocTracker::new();
+        for _ in 0..10 {
+            tracker.record(false, false);
+        }
+        assert!(!tracker.forced_mono);
+        assert_eq!(tracker.record(true, false), AllocChange::Recovered(10));
+    }
+
+    #[test]
+    fn alloc_recovery_goes_back_to_stereo() {
+        let mut tracker = AllocTracker::new();
+        record_run(&mut tracker, false, AUDIO_MONO_FALLBACK_FAILURES + 2);
+        assert!(tracker.forced_mono);
+
+        // a failure part way through starts the wait for stereo over
+        assert_eq!(record_run(&mut tracker, true, AUDIO_STEREO_RETRY_BUFFERS - 1), vec![AllocChange::Recovered(2)]);
+        assert_eq!(record_run(&mut tracker, false, 1), vec![AllocChange::Failing]);
+        assert!(record_run(&mut tracker, true, AUDIO_STEREO_RETRY_BUFFERS - 1).is_empty());
+        assert!(tracker.forced_mono);
+
+        assert_eq!(tracker.record(true, true), AllocChange::BackToStereo);
+        assert!(!tracker.forced_mono);
+
+        // & it can fall back again later on
+        assert_eq!(record_run(&mut tracker, false, AUDIO_MONO_FALLBACK_FAILURES), vec![AllocChange::Failing, AllocChange::FallBackToMono]);
+    }
+
+    extern "C" {
+        fn doom_init_framebuffers();
+        fn I_InitGraphics();
+        fn I_SetPalette(palette: *const u8);
+        static screens: [*mut u8;5];
+    }
+
+    /// DOOM's screen expanded through the given palette, as (indexed, RGBA) framebuffers
+    fn framebuffers_with_palette(palette: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let (width, height) = doom_screen_size();
+        let pixels = (width * height) as usize;
+        unsafe {
+            I_SetPalette(palette.as_ptr());
+            let indexed = std::slice::from_raw_parts(doom_get_framebuffer(1) as *const u8, pixels).to_vec();
+            let rgba = std::slice::from_raw_parts(doom_get_framebuffer(DOOM_FB_CHANNELS) as *const u8, pixels * 4).to_vec();
+            (indexed, rgba)
+        }
+    }
+
+    #[test]
+    fn palette_tints_are_in_the_rgba_framebuffer() {
+        unsafe {
+            doom_set_malloc(doom_malloc, doom_free);
+            doom_init_framebuffers();
+            I_InitGraphics();
+        }
+
+        // a screen using every palette index
+        let (width, height) = doom_screen_size();
+        let pixels = (width * height) as usize;
+        let screen: Vec<u8> = (0..pixels).map(|i| (i % 256) as u8).collect();
+        unsafe { ptr::copy_nonoverlapping(screen.as_ptr(), screens[0], pixels) };
+
+        // a stand-in for PLAYPAL's first palette, & its first pain palette (every color moved 1/9 of the way to pure red, which is how PLAYPAL's are made)
+        let base: Vec<u8> = (0..=255u8).flat_map(|i| [i, 255 - i, i / 2]).collect();
+        let red: Vec<u8> = base.chunks_exact(3).flat_map(|c| [c[0] + (255 - c[0]) / 9, c[1] - c[1] / 9, c[2] - c[2] / 9]).collect();
+
+        let (base_indexed, base_rgba) = framebuffers_with_palette(&base);
+        let (red_indexed, red_rgba) = framebuffers_with_palette(&red);
+
+        // the indexed framebuffer doesn't change when the palette does, so it never shows a tint
+        assert_eq!(base_indexed, screen);
+        assert_eq!(red_indexed, screen);
+
+        // the RGBA one (which is what gets drawn) comes through whichever palette is current, tint included
+        for (i, index) in screen.iter().enumerate() {
+            let color = *index as usize * 3;
+            assert_eq!(base_rgba[i * 4..i * 4 + 4], [base[color], base[color + 1], base[color + 2], 255], "pixel {}", i);
+            assert_eq!(red_rgba[i * 4..i * 4 + 4], [red[color], red[color + 1], red[color + 2], 255], "pixel {}", i);
+        }
+        assert_ne!(base_rgba, red_rgba);
+    }
+
+    #[test]
+    fn gamma_lut_keeps_the_ends_fixed() {
+        for gamma in [0.5, 1.0, 1.8, 2.2] {
+            let lut = gamma_lut(gamma);
+            assert_eq!((lut[0], lut[255]), (0, 255), "gamma {}", gamma);
+            assert!(lut.windows(2).all(|pair| pair[0] <= pair[1]), "gamma {} isn't increasing", gamma);
+        }
+    }
+
+    #[test]
+    fn gamma_lut_known_values() {
+        let identity = gamma_lut(1.0);
+        assert!(identity.iter().enumerate().all(|(i, v)| *v as usize == i));
+
+        // (128 / 255) ^ (1 / 2) * 255 = 180.7
+        assert_eq!(gamma_lut(2.0)[128], 181);
+        // (128 / 255) ^ 2 * 255 = 64.25
+        assert_eq!(gamma_lut(0.5)[128], 64);
+    }
+
+    #[test]
+    fn rgb565_known_colors() {
+        assert_eq!(pack_rgb565(0, 0, 0), 0x0000);
+        assert_eq!(pack_rgb565(255, 255, 255), 0xFFFF);
+        assert_eq!(pack_rgb565(255, 0, 0), 0xF800);
+        assert_eq!(pack_rgb565(0, 255, 0), 0x07E0);
+        assert_eq!(pack_rgb565(0, 0, 255), 0x001F);
+        // the low bits just drop off
+        assert_eq!(pack_rgb565(0x87, 0x43, 0x21), (0x10 << 11) | (0x10 << 5) | 0x04);
+        assert_eq!(pack_rgb565(7, 3, 7), 0x0000);
+    }
+
+    #[test]
+    fn quicksave_slot_goes_back_after_doom_reads_the_keys() {
+        let mut borrowed = Some(BorrowedQuicksave { slot: 2, sent_tic: None });
+
+        // still sending keys
+        assert_eq!(quicksave_restore_due(&mut borrowed, true, 10), None);
+        // all sent, but DOOM hasn't run a tic since
+        assert_eq!(quicksave_restore_due(&mut borrowed, false, 10), None);
+        assert_eq!(quicksave_restore_due(&mut borrowed, false, 10), None);
+        // now it has
+        assert_eq!(quicksave_restore_due(&mut borrowed, false, 11), Some(2));
+        assert!(borrowed.is_none());
+        assert_eq!(quicksave_restore_due(&mut borrowed, false, 12), None);
+    }
+
+    #[test]
+    fn more_keys_queued_hold_off_the_quicksave_slot() {
+        let mut borrowed = Some(BorrowedQuicksave { slot: -1, sent_tic: None });
+        assert_eq!(quicksave_restore_due(&mut borrowed, false, 10), None);
+        assert_eq!(quicksave_restore_due(&mut borrowed, true, 11), None);
+        assert_eq!(quicksave_restore_due(&mut borrowed, false, 11), None);
+        assert_eq!(quicksave_restore_due(&mut borrowed, false, 12), Some(-1));
+    }
+
+    // no save files on disk: nothing opens, so the rest never get a handle to work with
+    unsafe extern "C" fn no_save_files(_filename: *const c_char, _mode: *const c_char) -> i32 { 0 }
+    unsafe extern "C" fn no_close(_handle: i32) {}
+    unsafe extern "C" fn no_read(_handle: i32, _buf: *mut c_void, _count: i32) -> i32 { 0 }
+    unsafe extern "C" fn no_write(_handle: i32, _buf: *const c_void, _count: i32) -> i32 { 0 }
+    unsafe extern "C" fn no_seek(_handle: i32, _offset: i32, _origin: i32) -> i32 { -1 }
+    unsafe extern "C" fn no_tell(_handle: i32) -> i32 { -1 }
+    unsafe extern "C" fn no_eof(_handle: i32) -> i32 { 1 }
+
+    /// Run an autosave's quicksave keys through a key queue the way the app does (with DOOM running a tic every frame),
+    /// returning DOOM's quicksave slot as of when each key went out, followed by the slot once it's all done
+    fn run_autosave(autosave_slot: i32) -> (Vec<i32>, i32) {
+        let mut borrowed = Some(BorrowedQuicksave { slot: unsafe { doom_get_quicksave_slot() }, sent_tic: None });
+        unsafe { doom_set_quicksave_slot(autosave_slot, b"AUTOSAVE\0".as_ptr() as *const c_char) };
+
+        let mut queue = KeyQueue::new();
+        queue.push_tap(input::KEY_F6, 0);
+        queue.push_tap(input::KEY_Y, 2);
+
+        let mut slots_read = Vec::new();
+        for input_tic in 0..16 {
+            queue.dispatch(|_| slots_read.push(unsafe { doom_get_quicksave_slot() }));
+            if let Some(slot) = quicksave_restore_due(&mut borrowed, !queue.is_empty(), input_tic) {
+                unsafe { doom_set_quicksave_slot(slot, b"\0".as_ptr() as *const c_char) };
+            }
+        }
+        assert!(borrowed.is_none());
+        (slots_read, unsafe { doom_get_quicksave_slot() })
+    }
+
+    #[test]
+    fn manual_quicksave_after_an_autosave_uses_the_players_slot() {
+        unsafe {
+            doom_set_malloc(doom_malloc, doom_free);
+            doom_set_file_io(no_save_files, no_close, no_read, no_write, no_seek, no_tell, no_eof);
+            doom_set_quicksave_slot(2, b"MINE\0".as_ptr() as *const c_char);
+        }
+
+        // every key of the autosave goes out while quicksave points at the autosave slot, & then it's back on the player's
+        let (slots_read, slot_after) = run_autosave(5);
+        assert_eq!(slots_read, vec![5;4]);
+        assert_eq!(slot_after, 2);
+
+        // a player who hadn't picked a slot yet still gets asked for one
+        unsafe { doom_set_quicksave_slot(-1, b"\0".as_ptr() as *const c_char) };
+        let (_, slot_after) = run_autosave(5);
+        assert_eq!(slot_after, -1);
+    }
+
+    const USE_OR_SELECT: [i32;2] = [input::KEY_SPACE, input::KEY_ENTER];
+
+    #[test]
+    fn context_a_button_picks_select_in_the_menu() {
+        assert_eq!(resolve_keys_for(&USE_OR_SELECT, AButtonMode::Context, true), vec![input::KEY_ENTER]);
+    }
+
+    #[test]
+    fn context_a_button_picks_use_in_game_and_on_the_automap() {
+        // the automap isn't a menu, so it gets use just like gameplay does
+        assert_eq!(resolve_keys_for(&USE_OR_SELECT, AButtonMode::Context, false), vec![input::KEY_SPACE]);
+    }
+
+    #[test]
+    fn a_button_sends_both_outside_context_mode() {
+        for menu_active in [false, true] {
+            assert_eq!(resolve_keys_for(&USE_OR_SELECT, AButtonMode::Both, menu_active), USE_OR_SELECT.to_vec());
+        }
+    }
+
+    #[test]
+    fn context_mode_leaves_other_bindings_alone() {
+        assert_eq!(resolve_keys_for(&[input::KEY_SPACE], AButtonMode::Context, true), vec![input::KEY_SPACE]);
+        assert_eq!(resolve_keys_for(&[input::KEY_ENTER, bindings::KEY_CTRL], AButtonMode::Context, false), vec![input::KEY_ENTER, bindings::KEY_CTRL]);
+    }
+
+    #[test]
+    fn quad_uvs_rotate_and_mirror_the_image() {
+        // DOOM's screen on a power of two canvas
+        let (max_u, max_v) = (320.0 / 512.0, 200.0 / 256.0);
+        let (top_left, top_right, bottom_right, bottom_left) = ((0.0, 0.0), (max_u, 0.0), (max_u, max_v), (0.0, max_v));
+
+        // which corner of the image each corner of the screen shows, clockwise from the top left
+        let rotated = [
+            [top_left, top_right, bottom_right, bottom_left],
+            [bottom_left, top_left, top_right, bottom_right],
+            [bottom_right, bottom_left, top_left, top_right],
+            [top_right, bottom_right, bottom_left, top_left],
+        ];
+
+        for (turns, expected) in rotated.iter().enumerate() {
+            for flip_h in [false, true] {
+                for flip_v in [false, true] {
+                    let expected = expected.map(|(u, v)| (if flip_h { max_u - u } else { u }, if flip_v { max_v - v } else { v }));
+                    assert_eq!(quad_uvs(turns, flip_h, flip_v, (max_u, max_v)), expected, "{} turns, flip_h {}, flip_v {}", turns, flip_h, flip_v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quad_uvs_mirror_before_rotating() {
+        // mirrored left to right then turned a quarter, the screen's top left shows what was the image's bottom right
+        let uvs = quad_uvs(1, true, false, (1.0, 1.0));
+        assert_eq!(uvs, [(1.0, 1.0), (1.0, 0.0), (0.0, 0.0), (0.0, 1.0)]);
+    }
+
+    /// Runs numbered chunks through an audio queue of the given depth, returning every chunk that comes out to be submitted
+    fn run_audio_queue(chunks: &[Vec<i16>], depth: usize) -> Vec<Vec<i16>> {
+        let mut queue = VecDeque::new();
+        let mut crossfade = None;
+        let mut last_sample = 0;
+        chunks.iter().filter_map(|chunk| next_audio_chunk(&mut queue, chunk.clone(), depth, &mut crossfade, &mut last_sample)).collect()
+    }
+
+    fn numbered_chunks(count: i16) -> Vec<Vec<i16>> {
+        (0..count).map(|i| (0..4).map(|j| i * 100 + j).collect()).collect()
+    }
+
+    #[test]
+    fn audio_chunks_end_with_the_next_chunks_first_sample() {
+        let chunks = numbered_chunks(8);
+
+        for depth in 1..=3 {
+            let submitted = run_audio_queue(&chunks, depth);
+            assert_eq!(submitted.len(), chunks.len() - depth);
+
+            for (i, chunk) in submitted.iter().enumerate() {
+                assert_eq!(chunk[..4], chunks[i][..], "depth {}", depth);
+                assert_eq!(chunk[chunk.len() - 1], chunks[i + 1][0], "depth {}", depth);
+            }
+            for pair in submitted.windows(2) {
+                assert_eq!(pair[0][pair[0].len() - 1], pair[1][0], "depth {}", depth);
+            }
+        }
+    }
+
+    #[test]
+    fn lowering_audio_queue_depth_drops_stale_chunks() {
+        let chunks = numbered_chunks(6);
+        let mut queue = VecDeque::new();
+        let mut crossfade = None;
+        let mut last_sample = 0;
+
+        for chunk in &chunks[..5] {
+            next_audio_chunk(&mut queue, chunk.clone(), 3, &mut crossfade, &mut last_sample);
+        }
+        assert_eq!(last_sample, chunks[2][0]);
+
+        // chunks 2 & 3 are dropped, so chunk 4 fades in from where chunk 1 left off (over its whole length, since it's so short)
+        let submitted = next_audio_chunk(&mut queue, chunks[5].clone(), 1, &mut crossfade, &mut last_sample).unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(submitted[0], (chunks[2][0] * 3 + chunks[4][0]) / 4);
+        assert_eq!(submitted[3], chunks[4][3]);
+        assert_eq!(submitted[4], chunks[5][0]);
+        assert_eq!(crossfade, None);
+    }
+}
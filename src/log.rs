@@ -0,0 +1,78 @@
+use std::sync::{Mutex, atomic::{AtomicU8, Ordering}};
+
+use dbsdk_rs::db;
+use lazy_static::lazy_static;
+
+/// Severity of a DOOM log message. As a filter, only messages at or above the given level are forwarded (LogLevel::None hides everything)
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Info,
+    Warning,
+    Error,
+    None,
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+lazy_static! {
+    // DOOM prints most lines in several pieces, so they're collected here until the newline arrives
+    static ref PENDING_LINE: Mutex<String> = Mutex::new(String::new());
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+fn log_level() -> u8 {
+    LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Best guess at the severity of a line of DOOM output, since DOOM doesn't tag its messages
+pub fn classify(line: &str) -> LogLevel {
+    let line = line.to_ascii_lowercase();
+
+    if ["error", "can't", "couldn't", "failed", "not found"].iter().any(|v| line.contains(v)) {
+        LogLevel::Error
+    }
+    else if ["warning", "bad ", "unknown"].iter().any(|v| line.contains(v)) {
+        LogLevel::Warning
+    }
+    else {
+        LogLevel::Info
+    }
+}
+
+fn emit(line: &str) {
+    if line.trim().is_empty() {
+        return;
+    }
+
+    if classify(line) as u8 >= log_level() {
+        db::log(line);
+    }
+}
+
+/// Forward a piece of DOOM output to the log, one complete line at a time
+pub fn print(text: &str) {
+    let mut pending = PENDING_LINE.lock().unwrap();
+    pending.push_str(text);
+
+    while let Some(idx) = pending.find('\n') {
+        let line: String = pending.drain(..=idx).collect();
+        emit(line.trim_end());
+    }
+}
+
+/// Log a message DOOM showed on the HUD. These are always info level, whatever they say
+pub fn hud_message(text: &str) {
+    if LogLevel::Info as u8 >= log_level() {
+        db::log(format!("HUD: {}", text).as_str());
+    }
+}
+
+/// Forward whatever is left of an unfinished line
+pub fn flush() {
+    let mut pending = PENDING_LINE.lock().unwrap();
+    emit(pending.as_str());
+    pending.clear();
+}
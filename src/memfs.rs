@@ -0,0 +1,251 @@
+use std::{collections::HashMap, sync::{Mutex, atomic::{AtomicBool, Ordering}}};
+
+use lazy_static::lazy_static;
+
+use files::OpenMode;
+
+// handles for in-memory files are kept well clear of the range the real filesystem hands out
+const FIRST_HANDLE: i32 = 0x4000_0000;
+
+/// Which end (or point) of a file a seek is relative to, matching DOOM's doom_seek_t
+#[derive(Clone, Copy)]
+pub enum Whence {
+    Begin,
+    Current,
+    End,
+}
+
+struct OpenFile {
+    path: String,
+    pos: usize,
+}
+
+/// Files kept in memory, standing in for the memory card when it can't be written to. <br/>
+/// Anything saved here is gone once the game is turned off, but saving & loading within a session still works
+#[derive(Default)]
+struct MemFiles {
+    files: HashMap<String, Vec<u8>>,
+    open: HashMap<i32, OpenFile>,
+    next_handle: i32,
+}
+
+lazy_static! {
+    static ref MEM_FILES: Mutex<MemFiles> = Mutex::new(MemFiles { next_handle: FIRST_HANDLE, ..Default::default() });
+}
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Start redirecting saves into memory
+pub fn activate() {
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+pub fn is_mem_handle(handle: i32) -> bool {
+    handle >= FIRST_HANDLE
+}
+
+/// Open an in-memory file, returning 0 if it's opened for reading & doesn't exist. Opening for writing starts the file over, & appending starts at the end
+pub fn open(path: &str, mode: OpenMode) -> i32 {
+    let mut mem = MEM_FILES.lock().unwrap();
+
+    let pos = match mode {
+        OpenMode::Read => match mem.files.get(path) {
+            Some(_) => 0,
+            None => return 0,
+        },
+        OpenMode::Write => {
+            mem.files.insert(path.to_string(), Vec::new());
+            0
+        }
+        OpenMode::Append => mem.files.entry(path.to_string()).or_default().len(),
+    };
+
+    let handle = mem.next_handle;
+    mem.next_handle += 1;
+    mem.open.insert(handle, OpenFile { path: path.to_string(), pos });
+    handle
+}
+
+pub fn close(handle: i32) {
+    MEM_FILES.lock().unwrap().open.remove(&handle);
+}
+
+pub fn read(handle: i32, buf: &mut [u8]) -> i32 {
+    let mut mem = MEM_FILES.lock().unwrap();
+    let MemFiles { files, open, .. } = &mut *mem;
+
+    let file = match open.get_mut(&handle) {
+        Some(v) => v,
+        None => return -1,
+    };
+    let data = &files[&file.path];
+
+    // seeked past the end, or another handle started the file over underneath this one
+    if file.pos >= data.len() {
+        return 0;
+    }
+
+    let count = buf.len().min(data.len().saturating_sub(file.pos));
+    buf[..count].copy_from_slice(&data[file.pos..file.pos + count]);
+    file.pos += count;
+    count as i32
+}
+
+pub fn write(handle: i32, buf: &[u8]) -> i32 {
+    let mut mem = MEM_FILES.lock().unwrap();
+    let MemFiles { files, open, .. } = &mut *mem;
+
+    let file = match open.get_mut(&handle) {
+        Some(v) => v,
+        None => return -1,
+    };
+    let data = files.get_mut(&file.path).unwrap();
+
+    // writing past the end (after seeking there) fills the gap with zeroes
+    if data.len() < file.pos + buf.len() {
+        data.resize(file.pos + buf.len(), 0);
+    }
+    data[file.pos..file.pos + buf.len()].copy_from_slice(buf);
+    file.pos += buf.len();
+    buf.len() as i32
+}
+
+pub fn seek(handle: i32, offset: i32, whence: Whence) -> i32 {
+    let mut mem = MEM_FILES.lock().unwrap();
+    let MemFiles { files, open, .. } = &mut *mem;
+
+    let file = match open.get_mut(&handle) {
+        Some(v) => v,
+        None => return -1,
+    };
+
+    let base = match whence {
+        Whence::Begin => 0,
+        Whence::Current => file.pos as i64,
+        Whence::End => files[&file.path].len() as i64,
+    };
+
+    let pos = base + offset as i64;
+    if pos < 0 {
+        return -1;
+    }
+
+    file.pos = pos as usize;
+    0
+}
+
+pub fn tell(handle: i32) -> i32 {
+    MEM_FILES.lock().unwrap().open.get(&handle).map(|v| v.pos as i32).unwrap_or(-1)
+}
+
+pub fn eof(handle: i32) -> bool {
+    let mem = MEM_FILES.lock().unwrap();
+
+    match mem.open.get(&handle) {
+        Some(file) => file.pos >= mem.files[&file.path].len(),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the files are shared between tests, so each test sticks to its own paths
+
+    fn read_all(handle: i32) -> Vec<u8> {
+        let mut buf = [0u8;64];
+        let count = read(handle, &mut buf);
+        buf[..count as usize].to_vec()
+    }
+
+    #[test]
+    fn written_file_reads_back() {
+        let w = open("rw.dsg", OpenMode::Write);
+        assert!(is_mem_handle(w));
+        assert_eq!(write(w, b"hello"), 5);
+        close(w);
+
+        let r = open("rw.dsg", OpenMode::Read);
+        assert_eq!(read_all(r), b"hello");
+        assert!(eof(r));
+        assert_eq!(read_all(r), b"");
+        close(r);
+
+        assert_eq!(open("missing.dsg", OpenMode::Read), 0);
+    }
+
+    #[test]
+    fn reading_after_seeking_past_the_end_reads_nothing() {
+        let w = open("seek.dsg", OpenMode::Write);
+        write(w, b"abc");
+        close(w);
+
+        let r = open("seek.dsg", OpenMode::Read);
+        assert_eq!(seek(r, 10, Whence::End), 0);
+        assert_eq!(tell(r), 13);
+        assert!(eof(r));
+        assert_eq!(read_all(r), b"");
+
+        assert_eq!(seek(r, -1, Whence::Begin), -1);
+        assert_eq!(seek(r, -2, Whence::End), 0);
+        assert_eq!(read_all(r), b"bc");
+        close(r);
+    }
+
+    #[test]
+    fn writing_past_the_end_fills_the_gap_with_zeroes() {
+        let w = open("gap.dsg", OpenMode::Write);
+        write(w, b"ab");
+        seek(w, 2, Whence::Current);
+        write(w, b"cd");
+        close(w);
+
+        let r = open("gap.dsg", OpenMode::Read);
+        assert_eq!(read_all(r), b"ab\0\0cd");
+        close(r);
+    }
+
+    #[test]
+    fn append_writes_on_the_end() {
+        let w = open("append.dsg", OpenMode::Write);
+        write(w, b"one");
+        close(w);
+
+        let a = open("append.dsg", OpenMode::Append);
+        assert_eq!(tell(a), 3);
+        write(a, b"two");
+        close(a);
+
+        let r = open("append.dsg", OpenMode::Read);
+        assert_eq!(read_all(r), b"onetwo");
+        close(r);
+    }
+
+    #[test]
+    fn opening_for_writing_starts_the_file_over() {
+        let w = open("truncate.dsg", OpenMode::Write);
+        write(w, b"a long save");
+        close(w);
+
+        let r = open("truncate.dsg", OpenMode::Read);
+        assert_eq!(seek(r, 5, Whence::Begin), 0);
+
+        // the file shrinks out from under the reader
+        let w = open("truncate.dsg", OpenMode::Write);
+        write(w, b"new");
+        close(w);
+
+        assert!(eof(r));
+        assert_eq!(read_all(r), b"");
+        close(r);
+
+        let r = open("truncate.dsg", OpenMode::Read);
+        assert_eq!(read_all(r), b"new");
+        close(r);
+    }
+}
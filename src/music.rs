@@ -0,0 +1,347 @@
+use std::{collections::{HashMap, HashSet}, sync::{Mutex, atomic::{AtomicBool, AtomicU32, Ordering}}};
+
+use dbsdk_rs::{db, io, audio::{self, AudioSample, AudioVoiceParam}};
+use lazy_static::lazy_static;
+
+use files;
+use wav::{self, WavData};
+
+/// Volume the MIDI synth plays music at by default (before master volume & ducking)
+pub const DEFAULT_MUSIC_VOLUME: f32 = 0.2;
+
+// whether a music track is currently playing (doom_playmus mutes the synth when it can't find a track)
+static MUSIC_PLAYING: AtomicBool = AtomicBool::new(false);
+
+pub fn set_music_playing(playing: bool) {
+    MUSIC_PLAYING.store(playing, Ordering::Relaxed);
+}
+
+pub fn music_playing() -> bool {
+    MUSIC_PLAYING.load(Ordering::Relaxed)
+}
+
+// cleared if the synth couldn't be set up (no usable soundfont), in which case there's no music at all
+static MUSIC_AVAILABLE: AtomicBool = AtomicBool::new(true);
+
+pub fn set_music_available(available: bool) {
+    MUSIC_AVAILABLE.store(available, Ordering::Relaxed);
+}
+
+pub fn music_available() -> bool {
+    MUSIC_AVAILABLE.load(Ordering::Relaxed)
+}
+
+// mute toggle, which doom_playmus needs to respect when it starts a new track
+static MUSIC_MUTED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_music_muted(muted: bool) {
+    MUSIC_MUTED.store(muted, Ordering::Relaxed);
+}
+
+pub fn music_muted() -> bool {
+    MUSIC_MUTED.load(Ordering::Relaxed)
+}
+
+// whether music goes through the reverb unit, which play_streamed needs when it starts a new track
+static MUSIC_REVERB: AtomicBool = AtomicBool::new(false);
+
+/// Route music (both the MIDI synth & recorded tracks) through the reverb unit or not. Recorded tracks pick it up when the next one starts
+pub fn set_music_reverb(enabled: bool) {
+    MUSIC_REVERB.store(enabled, Ordering::Relaxed);
+    audio::set_midi_reverb(enabled);
+}
+
+pub fn music_reverb() -> bool {
+    MUSIC_REVERB.load(Ordering::Relaxed)
+}
+
+// master volume, which doom_playmus also needs when it starts a new track. stored as f32 bits (starts at 1.0)
+static MASTER_VOLUME: AtomicU32 = AtomicU32::new(0x3f80_0000);
+
+pub fn set_master_volume(volume: f32) {
+    MASTER_VOLUME.store(volume.to_bits(), Ordering::Relaxed);
+}
+
+pub fn master_volume() -> f32 {
+    f32::from_bits(MASTER_VOLUME.load(Ordering::Relaxed))
+}
+
+// music volume, which carries over from one track to the next. stored as f32 bits (starts at DEFAULT_MUSIC_VOLUME)
+static MUSIC_VOLUME: AtomicU32 = AtomicU32::new(0x3e4c_cccd);
+
+pub fn set_music_volume(volume: f32) {
+    MUSIC_VOLUME.store(volume.to_bits(), Ordering::Relaxed);
+}
+
+pub fn music_volume() -> f32 {
+    f32::from_bits(MUSIC_VOLUME.load(Ordering::Relaxed))
+}
+
+/// Volume a newly started track should play at, before any ducking
+pub fn track_volume() -> f32 {
+    if music_muted() { 0.0 } else { music_volume() * master_volume() }
+}
+
+lazy_static! {
+    // looping track doom_playmus last started (or found missing), so asking for it again doesn't restart it from the top
+    static ref CURRENT_TRACK: Mutex<Option<String>> = Mutex::new(None);
+    // tracks which have already been reported missing
+    static ref MISSING_TRACKS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Whether the given track is the one already playing (or already known to be missing) on a loop
+pub fn is_current_track(id: &str) -> bool {
+    CURRENT_TRACK.lock().unwrap().as_deref() == Some(id)
+}
+
+/// Remember which track is playing. Only looping tracks should be set here, since a one-shot track may have finished by the time it's asked for again
+pub fn set_current_track(id: Option<&str>) {
+    *CURRENT_TRACK.lock().unwrap() = id.map(|v| v.to_string());
+}
+
+/// The looping track that's playing, if there is one
+pub fn current_track() -> Option<String> {
+    CURRENT_TRACK.lock().unwrap().clone()
+}
+
+/// Log that a track's MIDI file is missing, the first time it's asked for
+pub fn warn_missing_track(id: &str) {
+    if MISSING_TRACKS.lock().unwrap().insert(id.to_string()) {
+        db::log(format!("Warning: no MIDI file for {} ({}), music will be silent", id, midi_path(id)).as_str());
+    }
+}
+
+pub fn midi_path(id: &str) -> String {
+    files::find_content(&format!("midi/{}.mid", id))
+}
+
+/// Voice recorded music plays on, well clear of the voices DOOM's sound effects are scheduled on
+pub const STREAM_VOICE: i32 = audio::VOICE_COUNT as i32 - 1;
+
+// how long a stopped recording's sample is kept around before being freed, so it can't be freed while the voice is still reading from it
+const STREAM_RELEASE_DELAY: f64 = 0.25;
+
+pub fn streamed_path(id: &str) -> String {
+    files::find_content(&format!("music/{}.wav", id))
+}
+
+/// Recorded music, played as a single sample on STREAM_VOICE in place of the MIDI. <br/>
+/// Switching tracks stops the voice, & the old sample waits in `stopped` until it's safe to free
+#[derive(Default)]
+struct StreamedMusic {
+    playing: Option<AudioSample>,
+    stopped: Vec<(AudioSample, f64)>,
+}
+
+impl StreamedMusic {
+    fn stop(&mut self) {
+        if let Some(sample) = self.playing.take() {
+            let t = audio::get_time();
+            audio::queue_stop_voice(STREAM_VOICE, t);
+            self.stopped.push((sample, t + STREAM_RELEASE_DELAY));
+        }
+    }
+}
+
+lazy_static! {
+    static ref STREAMED_MUSIC: Mutex<StreamedMusic> = Mutex::new(StreamedMusic::default());
+}
+
+/// Load a track's recording into audio memory. Returns None if there isn't one (or it couldn't be used, which gets logged)
+fn load_streamed(id: &str) -> Option<(AudioSample, usize)> {
+    let path = streamed_path(id);
+    if !io::file_exists(&path) {
+        return None;
+    }
+
+    let result = files::read_file_fully(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| wav::parse_wav(&bytes))
+        .and_then(|wav| {
+            let sample = match &wav.data {
+                WavData::Pcm16(samples) => AudioSample::create_s16(samples, wav.samplerate),
+                WavData::ImaAdpcm { data, block_size } => AudioSample::create_adpcm(data, *block_size, wav.samplerate),
+            };
+            sample.map(|v| (v, wav.length)).map_err(|_| "not enough audio memory".to_string())
+        });
+
+    match result {
+        Ok(v) => Some(v),
+        Err(e) => {
+            db::log(format!("Can't play {}, falling back to MIDI: {}", path, e).as_str());
+            None
+        }
+    }
+}
+
+/// Play a track's recording if it has one, stopping whichever recording was playing before. Returns false if there's no recording to play
+pub fn play_streamed(id: &str, looping: bool) -> bool {
+    let (sample, length) = match load_streamed(id) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    let mut streamed = STREAMED_MUSIC.lock().unwrap();
+    streamed.stop();
+
+    // the voice was just stopped at this same time, so starting it again afterwards cuts straight over to the new track
+    let t = audio::get_time();
+    audio::queue_set_voice_param_i(STREAM_VOICE, AudioVoiceParam::SampleData, sample.handle, t);
+    audio::queue_set_voice_param_i(STREAM_VOICE, AudioVoiceParam::Samplerate, sample.samplerate, t);
+    audio::queue_set_voice_param_i(STREAM_VOICE, AudioVoiceParam::LoopEnabled, looping as i32, t);
+    audio::queue_set_voice_param_i(STREAM_VOICE, AudioVoiceParam::LoopStart, 0, t);
+    audio::queue_set_voice_param_i(STREAM_VOICE, AudioVoiceParam::LoopEnd, length as i32, t);
+    audio::queue_set_voice_param_i(STREAM_VOICE, AudioVoiceParam::Reverb, music_reverb() as i32, t);
+    audio::queue_set_voice_param_f(STREAM_VOICE, AudioVoiceParam::Volume, track_volume(), t);
+    audio::queue_set_voice_param_f(STREAM_VOICE, AudioVoiceParam::Pitch, 1.0, t);
+    audio::queue_set_voice_param_f(STREAM_VOICE, AudioVoiceParam::Detune, 0.0, t);
+    audio::queue_set_voice_param_f(STREAM_VOICE, AudioVoiceParam::Pan, 0.0, t);
+    audio::queue_set_voice_param_f(STREAM_VOICE, AudioVoiceParam::FadeInDuration, 0.0, t);
+    audio::queue_set_voice_param_f(STREAM_VOICE, AudioVoiceParam::FadeOutDuration, 0.0, t);
+    audio::queue_start_voice(STREAM_VOICE, t);
+
+    streamed.playing = Some(sample);
+    true
+}
+
+/// Stop the recording that's playing, if there is one
+pub fn stop_streamed() {
+    STREAMED_MUSIC.lock().unwrap().stop();
+}
+
+/// Free the samples of stopped recordings once the voice is done with them. Called once a frame
+pub fn release_stopped_streams() {
+    let now = audio::get_time();
+    STREAMED_MUSIC.lock().unwrap().stopped.retain(|(_, release_time)| now < *release_time);
+}
+
+/// Whether the track that's playing is a recording (which doesn't go through the synth)
+pub fn is_streamed_playing() -> bool {
+    STREAMED_MUSIC.lock().unwrap().playing.is_some()
+}
+
+/// Set the volume of whichever track is playing, recorded or MIDI
+pub fn set_playing_volume(volume: f32) {
+    if is_streamed_playing() {
+        audio::queue_set_voice_param_f(STREAM_VOICE, AudioVoiceParam::Volume, volume, audio::get_time());
+    }
+    else {
+        audio::set_midi_volume(volume);
+    }
+}
+
+/// Read the MIDI file for the given music track from disc
+pub fn read_midi(id: &str) -> Option<Vec<u8>> {
+    files::read_file_fully(midi_path(id).as_str()).ok()
+}
+
+/// MIDI files kept in memory, so playing them doesn't have to hit the disc
+#[derive(Default)]
+pub struct MidiCache {
+    tracks: HashMap<String, Vec<u8>>,
+    size: usize,
+}
+
+impl MidiCache {
+    pub fn new() -> MidiCache {
+        MidiCache::default()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&[u8]> {
+        self.tracks.get(id).map(|v| v.as_slice())
+    }
+
+    pub fn insert(&mut self, id: &str, data: Vec<u8>) {
+        self.size += data.len();
+        if let Some(prev) = self.tracks.insert(id.to_string(), data) {
+            self.size -= prev.len();
+        }
+    }
+
+    /// Total size in bytes of every cached track
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// Loads every music track into a MidiCache, one track per step so the host can keep presenting frames (and show progress) in between. <br/>
+/// Tracks which would push the cache over the memory budget are skipped, and will just be streamed from disc when they're played
+pub struct MidiPreload {
+    ids: Vec<String>,
+    next: usize,
+    budget: usize,
+}
+
+impl MidiPreload {
+    pub fn new(ids: Vec<String>, budget: usize) -> MidiPreload {
+        MidiPreload { ids, next: 0, budget }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next >= self.ids.len()
+    }
+
+    /// Fraction of tracks processed so far, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        if self.ids.is_empty() {
+            return 1.0;
+        }
+
+        self.next as f32 / self.ids.len() as f32
+    }
+
+    /// Load the next track into the cache
+    pub fn step(&mut self, cache: &mut MidiCache) {
+        let id = match self.ids.get(self.next) {
+            Some(v) => v,
+            None => return,
+        };
+        self.next += 1;
+
+        // not every track exists in every IWAD, so missing files are expected here
+        let data = match read_midi(id) {
+            Some(v) => v,
+            None => return,
+        };
+
+        if cache.size() + data.len() > self.budget {
+            db::log(format!("Skipping preload of {} ({} bytes), MIDI cache budget exceeded", id, data.len()).as_str());
+            return;
+        }
+
+        cache.insert(id, data);
+    }
+}
+
+/// Sidechain envelope which ducks the music while loud sound effects are playing
+pub struct Ducker {
+    gain: f32,
+}
+
+impl Ducker {
+    pub fn new() -> Ducker {
+        Ducker { gain: 1.0 }
+    }
+
+    /// Gain which should currently be applied to the music
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Feed in the peak level (0 to 1) of the latest sound effect buffer. If it's over the threshold, the music drops by `amount` immediately
+    pub fn feed(&mut self, peak: f32, threshold: f32, amount: f32) {
+        if peak >= threshold {
+            self.gain = self.gain.min(1.0 - amount);
+        }
+    }
+
+    /// Ease the gain back up to full, taking `release` seconds to recover from a full duck
+    pub fn update(&mut self, delta: f32, amount: f32, release: f32) {
+        if release <= 0.0 {
+            self.gain = 1.0;
+        }
+        else {
+            self.gain = (self.gain + amount * delta / release).min(1.0);
+        }
+    }
+}
@@ -0,0 +1,712 @@
+use std::io::Write;
+
+use dbsdk_rs::{db, gamepad::GamepadButton, io::{FileMode, FileStream}, vdp::TextureFormat};
+
+use files;
+use log::LogLevel;
+use music;
+
+use resample::{self, ResampleMode};
+
+pub const OPTIONS_PATH: &str = "/cd/content/options.cfg";
+
+/// Settings which are adjusted in-game are written here, & override the options file when loaded
+pub const SETTINGS_PATH: &str = "/ma/dbdoom.cfg";
+
+// range of audio_latency_comp, in seconds - the upper bound decides how many audio buffers are kept alive (see audio_buffer_count)
+pub const MIN_LATENCY_COMP: f64 = -0.025;
+pub const MAX_LATENCY_COMP: f64 = 0.1;
+
+// range of audio_lookahead, in seconds. the lower bound keeps the lookahead above 0 with the most negative latency compensation
+const MIN_LOOKAHEAD: f64 = 0.03;
+const MAX_LOOKAHEAD: f64 = 0.5;
+
+// highest sound effect gain, which is already well into clipping for most scenes
+pub const MAX_AUDIO_GAIN: f32 = 16.0;
+
+// flags passed to doom_init, mirroring DOOM_FLAG_* in PureDOOM.h, with the names init_flags takes for them
+pub const DOOM_FLAG_HIDE_MOUSE_OPTIONS: i32 = 1;
+pub const DOOM_FLAG_HIDE_SOUND_OPTIONS: i32 = 2;
+pub const DOOM_FLAG_HIDE_MUSIC_OPTIONS: i32 = 4;
+pub const DOOM_FLAG_MENU_DARKEN_BG: i32 = 8;
+
+const INIT_FLAG_NAMES: [(&str, i32);4] = [
+    ("hide_mouse_options", DOOM_FLAG_HIDE_MOUSE_OPTIONS),
+    ("hide_sound_options", DOOM_FLAG_HIDE_SOUND_OPTIONS),
+    ("hide_music_options", DOOM_FLAG_HIDE_MUSIC_OPTIONS),
+    ("menu_darken_bg", DOOM_FLAG_MENU_DARKEN_BG),
+];
+
+// range of gamma, where values above 1 brighten the image
+pub const MIN_GAMMA: f32 = 0.5;
+pub const MAX_GAMMA: f32 = 2.0;
+
+// DOOM's save/load menus only show the first 6 of its 10 savegame slots
+const NUM_SAVE_SLOTS: i32 = 6;
+
+// slowest render_every_n allowed (every 6th frame is 10fps)
+const MAX_RENDER_EVERY_N: u32 = 6;
+
+/// Clockwise rotation applied to the output image, for displays which are mounted rotated
+#[derive(Clone, Copy, PartialEq)]
+pub enum Rotation {
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+impl Rotation {
+    /// Number of clockwise quarter turns
+    pub fn quarter_turns(self) -> usize {
+        match self {
+            Rotation::None => 0,
+            Rotation::Cw90 => 1,
+            Rotation::Cw180 => 2,
+            Rotation::Cw270 => 3,
+        }
+    }
+}
+
+/// Which keys the A button sends to DOOM
+#[derive(Clone, Copy, PartialEq)]
+pub enum AButtonMode {
+    /// Both space (use) & enter (menu select) at once
+    Both,
+    /// Enter while the menu is open, space otherwise
+    Context,
+}
+
+/// How the right stick turns the player
+#[derive(Clone, Copy, PartialEq)]
+pub enum TurnMode {
+    /// Mouse motion in proportion to how far the stick is pushed
+    Mouse,
+    /// The turn keys (left & right arrows), pressed once the stick is pushed past stick_threshold. DOOM ramps up key turning itself, just like on a keyboard
+    Keyboard,
+}
+
+/// Pixel format of the textures DOOM's frames are uploaded to
+#[derive(Clone, Copy, PartialEq)]
+pub enum CanvasFormat {
+    /// Full color, exactly as DOOM draws it
+    Rgba8888,
+    /// Half the texture memory, at the cost of some banding in dark gradients
+    Rgb565,
+}
+
+impl CanvasFormat {
+    pub fn texture_format(self) -> TextureFormat {
+        match self {
+            CanvasFormat::Rgba8888 => TextureFormat::RGBA8888,
+            CanvasFormat::Rgb565 => TextureFormat::RGB565,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            CanvasFormat::Rgba8888 => 4,
+            CanvasFormat::Rgb565 => 2,
+        }
+    }
+}
+
+/// Named presets for the performance-related options
+#[derive(Clone, Copy, PartialEq)]
+pub enum PerfProfile {
+    Quality,
+    Balanced,
+    Performance,
+}
+
+impl PerfProfile {
+    pub fn name(self) -> &'static str {
+        match self {
+            PerfProfile::Quality => "quality",
+            PerfProfile::Balanced => "balanced",
+            PerfProfile::Performance => "performance",
+        }
+    }
+
+    pub fn next(self) -> PerfProfile {
+        match self {
+            PerfProfile::Quality => PerfProfile::Balanced,
+            PerfProfile::Balanced => PerfProfile::Performance,
+            PerfProfile::Performance => PerfProfile::Quality,
+        }
+    }
+}
+
+/// Runtime options, loaded from a plain text file of `name=value` lines (lines starting with '#' are comments)
+pub struct Options {
+    /// Automatically quicksave once the player arrives at the next level
+    pub autosave: bool,
+    /// Savegame slot the autosave writes to
+    pub autosave_slot: i32,
+    /// Rotation of the output image
+    pub rotation: Rotation,
+    /// Rotate stick input along with the image, for setups where the controls are mounted with the display
+    pub rotate_input: bool,
+    /// Ask before a manual quicksave overwrites an existing save
+    pub confirm_overwrite: bool,
+    /// How DOOM's 11025 Hz sound effect mix is resampled for playback
+    pub audio_resample: ResampleMode,
+    /// Load every MIDI track into memory at boot, so changing music never has to wait on the disc
+    pub midi_preload: bool,
+    /// Memory budget (in KiB) for preloaded MIDI tracks
+    pub midi_preload_budget: usize,
+    /// Ignore input at boot until all buttons have been released, then wait for a button press
+    pub press_to_start: bool,
+    /// Seconds without any input before the game pauses & goes quiet (0 never does)
+    pub idle_timeout: f32,
+    /// Extra time (in seconds, may be negative) added to the audio lookahead to compensate for output latency
+    pub audio_latency_comp: f64,
+    /// How far ahead (in seconds) audio is scheduled. Higher values help avoid underruns on devices with more timing jitter, at the cost of latency
+    pub audio_lookahead: f64,
+    /// Lower the music volume while loud sound effects are playing
+    pub duck_music: bool,
+    /// Sound effect peak level (0 to 1) which triggers ducking
+    pub duck_threshold: f32,
+    /// How much the music volume is lowered by when ducked (0 to 1)
+    pub duck_amount: f32,
+    /// Time in seconds for the music to recover from being ducked
+    pub duck_release: f32,
+    /// Minimum severity of DOOM messages which get forwarded to the log
+    pub log_level: LogLevel,
+    /// Keep the title screen up instead of cycling through the demos
+    pub disable_attract: bool,
+    /// Cut straight between screens instead of melting
+    pub disable_wipes: bool,
+    /// Press use automatically on the intermission & finale screens, for kiosk & demo setups
+    pub auto_advance_screens: bool,
+    /// Seconds auto_advance_screens waits on each screen before pressing use
+    pub auto_advance_delay: f32,
+    /// Drive movement with the left stick's actual deflection instead of treating it like the movement keys
+    pub analog_move: bool,
+    /// Fall back to a single mono voice for sound effects if audio sample allocations keep failing
+    pub audio_mono_fallback: bool,
+    /// Show short on-screen notifications (mode changes, saves, errors, etc)
+    pub show_toasts: bool,
+    /// Mirror the output image horizontally (also flips turning & strafing to match)
+    pub flip_h: bool,
+    /// Mirror the output image vertically
+    pub flip_v: bool,
+    /// Button which toggles mute when pressed while holding Select
+    pub mute_button: GamepadButton,
+    /// Button which quicksaves (F6) when pressed with Select
+    pub quicksave_button: GamepadButton,
+    /// Button which quickloads (F9) when pressed with Select
+    pub quickload_button: GamepadButton,
+    /// Ask before a quickload throws away the current game
+    pub confirm_quickload: bool,
+    /// Demo to play at boot, as either a .lmp file in the content directory (or on the memory card) or a lump in a WAD, without the extension
+    pub playdemo: Option<String>,
+    /// Button which switches to the next soundfont when pressed with Select (none by default)
+    pub soundfont_button: Option<GamepadButton>,
+    /// Button which steps through game speeds (for debugging & reviewing demos) when pressed with Select (none by default)
+    pub speed_button: Option<GamepadButton>,
+    /// Pixel format of the screen textures
+    pub canvas_format: CanvasFormat,
+    /// Only upload & draw DOOM's screen every Nth frame (the game, input & audio still run every frame)
+    pub render_every_n: u32,
+    /// DOOM_FLAG_* bits passed to doom_init
+    pub init_flags: i32,
+    /// Whether all audio is muted
+    pub muted: bool,
+    /// Remember the mute state between sessions
+    pub persist_mute: bool,
+    /// Which keys the A button sends
+    pub a_button_mode: AButtonMode,
+    /// Whether the right stick turns like a mouse or like the turn keys
+    pub turn_mode: TurnMode,
+    /// Number of sound effect chunks held back before being submitted (more = smoother on jittery systems, at the cost of latency)
+    pub audio_queue_depth: usize,
+    /// Selected performance profile
+    pub profile: PerfProfile,
+    /// Overall volume (0 to 1) applied to both sound effects & music
+    pub master_volume: f32,
+    /// Volume (0 to 1) of the music on its own, before master_volume is applied
+    pub music_volume: f32,
+    /// Shrink the image to keep it at target_aspect, instead of stretching it over the whole display
+    pub pillarbox: bool,
+    /// Aspect ratio the image is shown at when pillarboxed (or rotated)
+    pub target_aspect: f32,
+    /// Brightness curve applied to the image (1 leaves it untouched)
+    pub gamma: f32,
+    /// Run by default, with the run button walking instead
+    pub always_run: bool,
+    /// How far (0 to 1) the left & right sound effect voices are panned from center. 0 collapses them to mono
+    pub stereo_width: f32,
+    /// How much reverb (0-1) music & sound effects get (0 is none)
+    pub reverb_amount: f32,
+    /// Cutoff (in Hz) of a low-pass filter softening DOOM's sound effects. 0 turns it off
+    pub lowpass_cutoff: f32,
+    /// Multiplier applied to DOOM's sound effect mix, which is clipped if it goes past full scale
+    pub audio_gain: f32,
+    /// Show the FPS counter from boot (it can also be toggled in-game)
+    pub show_fps: bool,
+    /// Time host-side effects (toasts, music ducking etc) by the measured frame time, instead of assuming every frame is 1/60s
+    pub measured_delta: bool,
+    /// Pulse the controller's rumble when firing & taking damage
+    pub rumble_enabled: bool,
+    /// How far above the level's usual redness (0-255) the screen has to flash to rumble for damage
+    pub rumble_threshold: f32,
+    /// Seconds a direction has to be held in the menus before it starts repeating
+    pub repeat_delay: f32,
+    /// Seconds between repeats of a held direction in the menus
+    pub repeat_rate: f32,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            autosave: false,
+            autosave_slot: NUM_SAVE_SLOTS - 1,
+            rotation: Rotation::None,
+            rotate_input: false,
+            confirm_overwrite: false,
+            audio_resample: ResampleMode::Hardware,
+            midi_preload: false,
+            midi_preload_budget: 2048,
+            press_to_start: false,
+            idle_timeout: 300.0,
+            audio_latency_comp: 0.0,
+            audio_lookahead: 0.05,
+            duck_music: false,
+            duck_threshold: 0.5,
+            duck_amount: 0.5,
+            duck_release: 0.5,
+            log_level: LogLevel::Info,
+            disable_attract: false,
+            disable_wipes: false,
+            auto_advance_screens: false,
+            auto_advance_delay: 5.0,
+            analog_move: false,
+            audio_mono_fallback: true,
+            show_toasts: true,
+            flip_h: false,
+            flip_v: false,
+            mute_button: GamepadButton::Y,
+            quicksave_button: GamepadButton::R1,
+            quickload_button: GamepadButton::L1,
+            confirm_quickload: false,
+            playdemo: None,
+            soundfont_button: None,
+            speed_button: None,
+            canvas_format: CanvasFormat::Rgba8888,
+            render_every_n: 1,
+            init_flags: 0,
+            muted: false,
+            persist_mute: false,
+            a_button_mode: AButtonMode::Both,
+            turn_mode: TurnMode::Mouse,
+            audio_queue_depth: 1,
+            profile: PerfProfile::Balanced,
+            master_volume: 1.0,
+            music_volume: music::DEFAULT_MUSIC_VOLUME,
+            stereo_width: 1.0,
+            reverb_amount: 0.0,
+            lowpass_cutoff: 0.0,
+            audio_gain: 4.0,
+            always_run: false,
+            pillarbox: false,
+            target_aspect: 4.0 / 3.0,
+            gamma: 1.0,
+            show_fps: false,
+            measured_delta: false,
+            rumble_enabled: false,
+            rumble_threshold: 8.0,
+            repeat_delay: 0.4,
+            repeat_rate: 0.1,
+        }
+    }
+}
+
+impl Options {
+    /// Apply the options file on top of the current values, followed by any saved settings
+    pub fn load_files(&mut self) {
+        // a profile only provides defaults for the options it covers, so it's applied before anything else no matter where it was set
+        let profile = find_setting(SETTINGS_PATH, "profile").or_else(|| find_setting(OPTIONS_PATH, "profile"));
+        if let Some(profile) = profile {
+            match parse_profile(&profile) {
+                Ok(v) => self.apply_profile(v),
+                Err(e) => db::log(format!("Bad profile setting: {}", e).as_str()),
+            }
+        }
+
+        self.apply_file(OPTIONS_PATH);
+        self.apply_file(SETTINGS_PATH);
+
+        if !self.persist_mute {
+            self.muted = false;
+        }
+    }
+
+    /// Apply options from the given file on top of the current values. Missing files and bad lines are skipped.
+    pub fn apply_file(&mut self, path: &str) {
+        read_config(path, |name, value| self.set(name, value));
+    }
+
+    /// Set every option covered by the given performance profile
+    pub fn apply_profile(&mut self, profile: PerfProfile) {
+        self.profile = profile;
+
+        match profile {
+            PerfProfile::Quality => {
+                self.audio_resample = ResampleMode::Linear;
+                self.audio_queue_depth = 2;
+                self.midi_preload = true;
+                self.log_level = LogLevel::Info;
+                self.render_every_n = 1;
+                self.canvas_format = CanvasFormat::Rgba8888;
+            }
+            PerfProfile::Balanced => {
+                self.audio_resample = ResampleMode::Hardware;
+                self.audio_queue_depth = 1;
+                self.midi_preload = false;
+                self.log_level = LogLevel::Info;
+                self.render_every_n = 1;
+                self.canvas_format = CanvasFormat::Rgba8888;
+            }
+            PerfProfile::Performance => {
+                self.audio_resample = ResampleMode::Hardware;
+                self.audio_queue_depth = 1;
+                self.midi_preload = false;
+                self.log_level = LogLevel::Error;
+                self.render_every_n = 2;
+                self.canvas_format = CanvasFormat::Rgb565;
+            }
+        }
+    }
+
+    /// Write the settings which can be adjusted in-game to the settings file
+    pub fn save_settings(&self) {
+        let mut text = format!("audio_latency_comp={}\nprofile={}\nmaster_volume={}\nmusic_volume={}\nalways_run={}\ngamma={}\n",
+            self.audio_latency_comp, self.profile.name(), self.master_volume, self.music_volume, self.always_run as i32, self.gamma);
+        if self.persist_mute {
+            text += format!("muted={}\n", self.muted as i32).as_str();
+        }
+
+        let result = FileStream::open(SETTINGS_PATH, FileMode::Write)
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|mut file| file.write_all(text.as_bytes()).map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            db::log(format!("Failed writing settings to {}: {}", SETTINGS_PATH, e).as_str());
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "autosave" => self.autosave = parse_bool(value)?,
+            "autosave_slot" => {
+                let slot: i32 = parse_num(value)?;
+                if !(0..NUM_SAVE_SLOTS).contains(&slot) {
+                    return Err(format!("autosave_slot must be between 0 and {}", NUM_SAVE_SLOTS - 1));
+                }
+                self.autosave_slot = slot;
+            }
+            "rotation" => {
+                self.rotation = match value {
+                    "0" => Rotation::None,
+                    "90" => Rotation::Cw90,
+                    "180" => Rotation::Cw180,
+                    "270" => Rotation::Cw270,
+                    _ => return Err(format!("rotation must be 0, 90, 180, or 270, got '{}'", value)),
+                };
+            }
+            "rotate_input" => self.rotate_input = parse_bool(value)?,
+            "confirm_overwrite" => self.confirm_overwrite = parse_bool(value)?,
+            "audio_resample" => {
+                self.audio_resample = match value {
+                    "hardware" => ResampleMode::Hardware,
+                    "linear" => ResampleMode::Linear,
+                    _ => return Err(format!("audio_resample must be hardware or linear, got '{}'", value)),
+                };
+            }
+            "midi_preload" => self.midi_preload = parse_bool(value)?,
+            "midi_preload_budget" => self.midi_preload_budget = parse_num(value)?,
+            "press_to_start" => self.press_to_start = parse_bool(value)?,
+            "idle_timeout" => {
+                let timeout: f32 = parse_num(value)?;
+                if !(timeout >= 0.0 && timeout.is_finite()) {
+                    return Err("idle_timeout must be 0 or more".to_string());
+                }
+                self.idle_timeout = timeout;
+            }
+            "audio_lookahead" => {
+                let lookahead: f64 = parse_num(value)?;
+                if !(MIN_LOOKAHEAD..=MAX_LOOKAHEAD).contains(&lookahead) {
+                    return Err(format!("audio_lookahead must be between {} and {}", MIN_LOOKAHEAD, MAX_LOOKAHEAD));
+                }
+                self.audio_lookahead = lookahead;
+            }
+            "audio_latency_comp" => {
+                let comp: f64 = parse_num(value)?;
+                if !(MIN_LATENCY_COMP..=MAX_LATENCY_COMP).contains(&comp) {
+                    return Err(format!("audio_latency_comp must be between {} and {}", MIN_LATENCY_COMP, MAX_LATENCY_COMP));
+                }
+                self.audio_latency_comp = comp;
+            }
+            "duck_music" => self.duck_music = parse_bool(value)?,
+            "duck_threshold" => self.duck_threshold = parse_fraction(value)?,
+            "duck_amount" => self.duck_amount = parse_fraction(value)?,
+            "duck_release" => {
+                let release: f32 = parse_num(value)?;
+                if release < 0.0 {
+                    return Err("duck_release can't be negative".to_string());
+                }
+                self.duck_release = release;
+            }
+            "log_level" => {
+                self.log_level = match value {
+                    "all" => LogLevel::Info,
+                    "warning" => LogLevel::Warning,
+                    "error" => LogLevel::Error,
+                    "none" => LogLevel::None,
+                    _ => return Err(format!("log_level must be all, warning, error, or none, got '{}'", value)),
+                };
+            }
+            "disable_attract" => self.disable_attract = parse_bool(value)?,
+            "disable_wipes" => self.disable_wipes = parse_bool(value)?,
+            "auto_advance_screens" => self.auto_advance_screens = parse_bool(value)?,
+            "auto_advance_delay" => {
+                let delay: f32 = parse_num(value)?;
+                if !(delay > 0.0 && delay.is_finite()) {
+                    return Err("auto_advance_delay must be above 0".to_string());
+                }
+                self.auto_advance_delay = delay;
+            }
+            "analog_move" => self.analog_move = parse_bool(value)?,
+            "audio_mono_fallback" => self.audio_mono_fallback = parse_bool(value)?,
+            "show_toasts" => self.show_toasts = parse_bool(value)?,
+            "flip_h" => self.flip_h = parse_bool(value)?,
+            "flip_v" => self.flip_v = parse_bool(value)?,
+            "mute_button" => self.mute_button = parse_button(value)?,
+            "quicksave_button" => self.quicksave_button = parse_button(value)?,
+            "quickload_button" => self.quickload_button = parse_button(value)?,
+            "confirm_quickload" => self.confirm_quickload = parse_bool(value)?,
+            "playdemo" => self.playdemo = if value == "none" { None } else { Some(value.to_string()) },
+            "soundfont_button" => self.soundfont_button = if value == "none" { None } else { Some(parse_button(value)?) },
+            "speed_button" => self.speed_button = if value == "none" { None } else { Some(parse_button(value)?) },
+            "canvas_format" => {
+                self.canvas_format = match value {
+                    "rgba8888" => CanvasFormat::Rgba8888,
+                    "rgb565" => CanvasFormat::Rgb565,
+                    _ => return Err(format!("canvas_format must be rgba8888 or rgb565, got '{}'", value)),
+                };
+            }
+            "render_every_n" => {
+                let n: u32 = parse_num(value)?;
+                if !(1..=MAX_RENDER_EVERY_N).contains(&n) {
+                    return Err(format!("render_every_n must be between 1 and {}", MAX_RENDER_EVERY_N));
+                }
+                self.render_every_n = n;
+            }
+            "init_flags" => self.init_flags = parse_init_flags(value)?,
+            "muted" => self.muted = parse_bool(value)?,
+            "persist_mute" => self.persist_mute = parse_bool(value)?,
+            "a_button_mode" => {
+                self.a_button_mode = match value {
+                    "both" => AButtonMode::Both,
+                    "context" => AButtonMode::Context,
+                    _ => return Err(format!("a_button_mode must be both or context, got '{}'", value)),
+                };
+            }
+            "turn_mode" => {
+                self.turn_mode = match value {
+                    "mouse" => TurnMode::Mouse,
+                    "keyboard" => TurnMode::Keyboard,
+                    _ => return Err(format!("turn_mode must be mouse or keyboard, got '{}'", value)),
+                };
+            }
+            "audio_queue_depth" => {
+                let depth: usize = parse_num(value)?;
+                if !(1..=3).contains(&depth) {
+                    return Err("audio_queue_depth must be between 1 and 3".to_string());
+                }
+                self.audio_queue_depth = depth;
+            }
+            // already applied by load_files
+            "profile" => self.profile = parse_profile(value)?,
+            "master_volume" => self.master_volume = parse_fraction(value)?,
+            "music_volume" => self.music_volume = parse_fraction(value)?,
+            "stereo_width" => self.stereo_width = parse_fraction(value)?,
+            "reverb_amount" => self.reverb_amount = parse_fraction(value)?,
+            "audio_gain" => {
+                let v: f32 = parse_num(value)?;
+                if !(0.0..=MAX_AUDIO_GAIN).contains(&v) {
+                    return Err(format!("audio_gain must be between 0 and {}", MAX_AUDIO_GAIN));
+                }
+                self.audio_gain = v;
+            }
+            "lowpass_cutoff" => {
+                // anything at or above the Nyquist frequency of DOOM's 11025 Hz output wouldn't filter anything
+                let nyquist = resample::DOOM_SAMPLERATE as f32 / 2.0;
+                let v: f32 = parse_num(value)?;
+                if !(0.0..nyquist).contains(&v) {
+                    return Err(format!("lowpass_cutoff must be 0 (off), or below {} Hz", nyquist));
+                }
+                self.lowpass_cutoff = v;
+            }
+            "always_run" => self.always_run = parse_bool(value)?,
+            "show_fps" => self.show_fps = parse_bool(value)?,
+            "measured_delta" => self.measured_delta = parse_bool(value)?,
+            "rumble_enabled" => self.rumble_enabled = parse_bool(value)?,
+            "rumble_threshold" => {
+                let threshold: f32 = parse_num(value)?;
+                if !(0.0..=255.0).contains(&threshold) {
+                    return Err("rumble_threshold must be between 0 and 255".to_string());
+                }
+                self.rumble_threshold = threshold;
+            }
+            "repeat_delay" => self.repeat_delay = parse_seconds(value)?,
+            "repeat_rate" => self.repeat_rate = parse_seconds(value)?,
+            "pillarbox" => self.pillarbox = parse_bool(value)?,
+            "target_aspect" => self.target_aspect = parse_aspect(value)?,
+            "gamma" => {
+                let gamma: f32 = parse_num(value)?;
+                if !(MIN_GAMMA..=MAX_GAMMA).contains(&gamma) {
+                    return Err(format!("gamma must be between {} and {}", MIN_GAMMA, MAX_GAMMA));
+                }
+                self.gamma = gamma;
+            }
+            _ => return Err(format!("unknown option '{}'", name)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Read a file of `name=value` lines (lines starting with '#' are comments), passing each one to `set` & logging any errors
+pub fn read_config<F: FnMut(&str, &str) -> Result<(), String>>(path: &str, mut set: F) {
+    let text = match read_text_file(path) {
+        Some(v) => v,
+        None => {
+            db::log(format!("No config file found at {}", path).as_str());
+            return;
+        }
+    };
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = match line.split_once('=') {
+            Some((name, value)) => set(name.trim(), value.trim()),
+            None => Err(format!("expected name=value, got '{}'", line)),
+        };
+
+        if let Err(e) = result {
+            db::log(format!("{}:{}: {}", path, line_idx + 1, e).as_str());
+        }
+    }
+}
+
+/// Find the value of a single setting in a config file, without logging anything if the file or setting is missing
+fn find_setting(path: &str, name: &str) -> Option<String> {
+    let text = read_text_file(path)?;
+
+    text.lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .rfind(|(n, _)| n.trim() == name)
+        .map(|(_, value)| value.trim().to_string())
+}
+
+pub fn read_text_file(path: &str) -> Option<String> {
+    String::from_utf8(files::read_file_fully(path).ok()?).ok()
+}
+
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "1" | "true" | "on" | "yes" => Ok(true),
+        "0" | "false" | "off" | "no" => Ok(false),
+        _ => Err(format!("expected a boolean, got '{}'", value)),
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("expected a number, got '{}'", value))
+}
+
+fn parse_fraction(value: &str) -> Result<f32, String> {
+    let v: f32 = parse_num(value)?;
+    if !(0.0..=1.0).contains(&v) {
+        return Err(format!("expected a number between 0 and 1, got '{}'", value));
+    }
+    Ok(v)
+}
+
+fn parse_seconds(value: &str) -> Result<f32, String> {
+    let v: f32 = parse_num(value)?;
+    if !(v > 0.0 && v.is_finite()) {
+        return Err(format!("expected a number of seconds above 0, got '{}'", value));
+    }
+    Ok(v)
+}
+
+/// Parse a comma separated list of doom_init flag names (see INIT_FLAG_NAMES), or none
+fn parse_init_flags(value: &str) -> Result<i32, String> {
+    if value == "none" {
+        return Ok(0);
+    }
+
+    value.split(',').map(|v| v.trim()).try_fold(0, |flags, name| {
+        match INIT_FLAG_NAMES.iter().find(|(v, _)| *v == name) {
+            Some((_, flag)) => Ok(flags | flag),
+            None => Err(format!("unknown init flag '{}'", name)),
+        }
+    })
+}
+
+/// Names of the doom_init flags which are set, for logging
+pub fn init_flag_names(flags: i32) -> String {
+    let names: Vec<&str> = INIT_FLAG_NAMES.iter().filter(|(_, flag)| flags & flag != 0).map(|(name, _)| *name).collect();
+    if names.is_empty() { "none".to_string() } else { names.join(", ") }
+}
+
+/// Parse an aspect ratio, given either as width:height or as a single number
+fn parse_aspect(value: &str) -> Result<f32, String> {
+    let aspect = match value.split_once(':') {
+        Some((w, h)) => parse_num::<f32>(w.trim())? / parse_num::<f32>(h.trim())?,
+        None => parse_num(value)?,
+    };
+
+    if !(aspect.is_finite() && aspect > 0.0) {
+        return Err(format!("expected an aspect ratio like 4:3 or 1.6, got '{}'", value));
+    }
+    Ok(aspect)
+}
+
+pub fn parse_button(value: &str) -> Result<GamepadButton, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "a" => Ok(GamepadButton::A),
+        "b" => Ok(GamepadButton::B),
+        "x" => Ok(GamepadButton::X),
+        "y" => Ok(GamepadButton::Y),
+        "up" => Ok(GamepadButton::Up),
+        "down" => Ok(GamepadButton::Down),
+        "left" => Ok(GamepadButton::Left),
+        "right" => Ok(GamepadButton::Right),
+        "l1" => Ok(GamepadButton::L1),
+        "l2" => Ok(GamepadButton::L2),
+        "l3" => Ok(GamepadButton::L3),
+        "r1" => Ok(GamepadButton::R1),
+        "r2" => Ok(GamepadButton::R2),
+        "r3" => Ok(GamepadButton::R3),
+        "start" => Ok(GamepadButton::Start),
+        "select" => Ok(GamepadButton::Select),
+        _ => Err(format!("unknown button '{}'", value)),
+    }
+}
+
+fn parse_profile(value: &str) -> Result<PerfProfile, String> {
+    match value {
+        "quality" => Ok(PerfProfile::Quality),
+        "balanced" => Ok(PerfProfile::Balanced),
+        "performance" => Ok(PerfProfile::Performance),
+        _ => Err(format!("profile must be quality, balanced, or performance, got '{}'", value)),
+    }
+}
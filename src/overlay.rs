@@ -0,0 +1,217 @@
+use dbsdk_rs::{vdp::{self, Color32, PackedVertex, Texture, TextureFormat}, math::{Vector2, Vector4}};
+
+// overlay coordinates are in pixels on a virtual 640x480 screen, with the origin in the top left
+pub const SCREEN_WIDTH: f32 = 640.0;
+pub const SCREEN_HEIGHT: f32 = 480.0;
+
+// glyphs are 5x7 pixels, each packed into an 8x8 cell of the font atlas
+// the atlas holds the 64 printable characters from ' ' to '_' (lowercase is drawn as uppercase), followed by a solid cell used for rectangles
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const CELL_SIZE: usize = 8;
+const ATLAS_COLUMNS: usize = 16;
+const ATLAS_WIDTH: usize = 128;
+const ATLAS_HEIGHT: usize = 64;
+const FIRST_GLYPH: u8 = b' ';
+const SOLID_CELL: usize = 64;
+
+/// Width in pixels of one character of text at scale 1, including spacing
+pub const CHAR_ADVANCE: f32 = (GLYPH_WIDTH + 1) as f32;
+/// Height in pixels of one line of text at scale 1, including spacing
+pub const LINE_HEIGHT: f32 = (GLYPH_HEIGHT + 2) as f32;
+
+const FONT: [[u8;GLYPH_HEIGHT];64] = [
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000], // ' '
+    [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100], // '!'
+    [0b01010, 0b01010, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000], // '"'
+    [0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010], // '#'
+    [0b00100, 0b01111, 0b10100, 0b01110, 0b00101, 0b11110, 0b00100], // '$'
+    [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011], // '%'
+    [0b01100, 0b10010, 0b10100, 0b01000, 0b10101, 0b10010, 0b01101], // '&'
+    [0b00100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000], // '\''
+    [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010], // '('
+    [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000], // ')'
+    [0b00000, 0b00100, 0b10101, 0b01110, 0b10101, 0b00100, 0b00000], // '*'
+    [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000], // '+'
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000], // ','
+    [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000], // '-'
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100], // '.'
+    [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000], // '/'
+    [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110], // '0'
+    [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110], // '1'
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111], // '2'
+    [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110], // '3'
+    [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010], // '4'
+    [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110], // '5'
+    [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110], // '6'
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000], // '7'
+    [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110], // '8'
+    [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100], // '9'
+    [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000], // ':'
+    [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b00100, 0b01000], // ';'
+    [0b00010, 0b00100, 0b01000, 0b10000, 0b01000, 0b00100, 0b00010], // '<'
+    [0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000], // '='
+    [0b01000, 0b00100, 0b00010, 0b00001, 0b00010, 0b00100, 0b01000], // '>'
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100], // '?'
+    [0b01110, 0b10001, 0b00001, 0b01101, 0b10101, 0b10101, 0b01110], // '@'
+    [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001], // 'A'
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110], // 'B'
+    [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110], // 'C'
+    [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100], // 'D'
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111], // 'E'
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000], // 'F'
+    [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111], // 'G'
+    [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001], // 'H'
+    [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110], // 'I'
+    [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100], // 'J'
+    [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001], // 'K'
+    [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111], // 'L'
+    [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001], // 'M'
+    [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001], // 'N'
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110], // 'O'
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000], // 'P'
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101], // 'Q'
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001], // 'R'
+    [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110], // 'S'
+    [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100], // 'T'
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110], // 'U'
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100], // 'V'
+    [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010], // 'W'
+    [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001], // 'X'
+    [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100], // 'Y'
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111], // 'Z'
+    [0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110], // '['
+    [0b00000, 0b10000, 0b01000, 0b00100, 0b00010, 0b00001, 0b00000], // '\\'
+    [0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110], // ']'
+    [0b00100, 0b01010, 0b10001, 0b00000, 0b00000, 0b00000, 0b00000], // '^'
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111], // '_'
+
+];
+
+/// Simple immediate-mode overlay for drawing text & rectangles on top of the game. <br/>
+/// Draw calls are batched up and submitted together by flush()
+pub struct Overlay {
+    font_tex: Texture,
+    vertices: Vec<PackedVertex>,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        let mut pixels: Vec<u8> = vec![0;ATLAS_WIDTH * ATLAS_HEIGHT * 4];
+
+        let mut set_pixel = |x: usize, y: usize| {
+            let idx = (y * ATLAS_WIDTH + x) * 4;
+            pixels[idx..idx + 4].copy_from_slice(&[255, 255, 255, 255]);
+        };
+
+        for (glyph_idx, glyph) in FONT.iter().enumerate() {
+            let (cell_x, cell_y) = Overlay::cell_origin(glyph_idx);
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                        set_pixel(cell_x + col, cell_y + row);
+                    }
+                }
+            }
+        }
+
+        let (solid_x, solid_y) = Overlay::cell_origin(SOLID_CELL);
+        for y in 0..CELL_SIZE {
+            for x in 0..CELL_SIZE {
+                set_pixel(solid_x + x, solid_y + y);
+            }
+        }
+
+        let font_tex = Texture::new(ATLAS_WIDTH as i32, ATLAS_HEIGHT as i32, false, TextureFormat::RGBA8888).unwrap();
+        font_tex.set_texture_data(0, &pixels);
+
+        Overlay {
+            font_tex,
+            vertices: Vec::new(),
+        }
+    }
+
+    fn cell_origin(cell: usize) -> (usize, usize) {
+        ((cell % ATLAS_COLUMNS) * CELL_SIZE, (cell / ATLAS_COLUMNS) * CELL_SIZE)
+    }
+
+    /// Measure the width in pixels of the given text
+    pub fn text_width(text: &str, scale: f32) -> f32 {
+        text.chars().count() as f32 * CHAR_ADVANCE * scale
+    }
+
+    fn push_quad(&mut self, rect: (f32, f32, f32, f32), uv_min: (f32, f32), uv_max: (f32, f32), color: Color32) {
+        let (x, y, w, h) = rect;
+        let to_ndc = |px: f32, py: f32| Vector4::new(px / SCREEN_WIDTH * 2.0 - 1.0, 1.0 - py / SCREEN_HEIGHT * 2.0, 0.0, 1.0);
+        let vtx = |px: f32, py: f32, u: f32, v: f32| PackedVertex::new(to_ndc(px, py), Vector2::new(u, v), color, Color32::new(0, 0, 0, 0));
+
+        let tl = vtx(x, y, uv_min.0, uv_min.1);
+        let tr = vtx(x + w, y, uv_max.0, uv_min.1);
+        let bl = vtx(x, y + h, uv_min.0, uv_max.1);
+        let br = vtx(x + w, y + h, uv_max.0, uv_max.1);
+
+        self.vertices.extend_from_slice(&[bl, br, tl, tl, br, tr]);
+    }
+
+    /// Draw a solid rectangle
+    pub fn draw_rect(&mut self, x: f32, y: f32, w: f32, h: f32, color: Color32) {
+        // sample from the middle of the solid cell so filtering never picks up neighboring glyphs
+        let (cell_x, cell_y) = Overlay::cell_origin(SOLID_CELL);
+        let u = (cell_x + CELL_SIZE / 2) as f32 / ATLAS_WIDTH as f32;
+        let v = (cell_y + CELL_SIZE / 2) as f32 / ATLAS_HEIGHT as f32;
+        self.push_quad((x, y, w, h), (u, v), (u, v), color);
+    }
+
+    /// Draw a single line of text with its top left corner at the given position
+    pub fn draw_text(&mut self, x: f32, y: f32, scale: f32, text: &str, color: Color32) {
+        let mut pen_x = x;
+        for ch in text.chars() {
+            let glyph = ch.to_ascii_uppercase() as u32;
+            let glyph_idx = if (FIRST_GLYPH as u32..FIRST_GLYPH as u32 + 64).contains(&glyph) { (glyph - FIRST_GLYPH as u32) as usize } else { (b'?' - FIRST_GLYPH) as usize };
+
+            if glyph_idx != 0 {
+                let (cell_x, cell_y) = Overlay::cell_origin(glyph_idx);
+                let uv_min = (cell_x as f32 / ATLAS_WIDTH as f32, cell_y as f32 / ATLAS_HEIGHT as f32);
+                let uv_max = ((cell_x + GLYPH_WIDTH) as f32 / ATLAS_WIDTH as f32, (cell_y + GLYPH_HEIGHT) as f32 / ATLAS_HEIGHT as f32);
+                self.push_quad((pen_x, y, GLYPH_WIDTH as f32 * scale, GLYPH_HEIGHT as f32 * scale), uv_min, uv_max, color);
+            }
+
+            pen_x += CHAR_ADVANCE * scale;
+        }
+    }
+
+    /// Draw a single line of text horizontally centered on the screen
+    pub fn draw_text_centered(&mut self, y: f32, scale: f32, text: &str, color: Color32) {
+        let x = ((SCREEN_WIDTH - Overlay::text_width(text, scale)) * 0.5).floor();
+        self.draw_text(x, y, scale, text, color);
+    }
+
+    /// Draw lines of text centered on the screen over a translucent backdrop
+    pub fn draw_panel(&mut self, scale: f32, lines: &[(&str, Color32)]) {
+        let line_spacing = LINE_HEIGHT * scale;
+        let padding = 8.0 * scale;
+        let width = lines.iter().map(|(text, _)| Overlay::text_width(text, scale)).fold(0.0, f32::max) + padding * 2.0;
+        let height = line_spacing * lines.len() as f32 + padding * 2.0;
+        let x = ((SCREEN_WIDTH - width) * 0.5).floor();
+        let y = ((SCREEN_HEIGHT - height) * 0.5).floor();
+
+        self.draw_rect(x, y, width, height, Color32::new(0, 0, 0, 192));
+        for (i, (text, color)) in lines.iter().enumerate() {
+            self.draw_text_centered(y + padding + line_spacing * i as f32, scale, text, *color);
+        }
+    }
+
+    /// Submit everything drawn since the last flush
+    pub fn flush(&mut self) {
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        vdp::blend_equation(vdp::BlendEquation::Add);
+        vdp::blend_func(vdp::BlendFactor::SrcAlpha, vdp::BlendFactor::OneMinusSrcAlpha);
+        vdp::bind_texture(Some(&self.font_tex));
+        vdp::draw_geometry_packed(vdp::Topology::TriangleList, &self.vertices);
+
+        self.vertices.clear();
+    }
+}
@@ -0,0 +1,112 @@
+use std::io::Write;
+
+use dbsdk_rs::{db, gamepad::{GamepadButton, GamepadButtonMask, GamepadState}, io::{FileMode, FileStream}};
+
+use files;
+
+// bytes per recorded frame: the button mask, then the left & right sticks' x & y, each a little-endian 16-bit value
+const FRAME_SIZE: usize = 10;
+
+const BUTTONS: [GamepadButton;16] = [
+    GamepadButton::A, GamepadButton::B, GamepadButton::X, GamepadButton::Y,
+    GamepadButton::Up, GamepadButton::Down, GamepadButton::Left, GamepadButton::Right,
+    GamepadButton::L1, GamepadButton::L2, GamepadButton::L3,
+    GamepadButton::R1, GamepadButton::R2, GamepadButton::R3,
+    GamepadButton::Select, GamepadButton::Start,
+];
+
+fn encode_frame(state: GamepadState) -> [u8;FRAME_SIZE] {
+    let mut frame = [0;FRAME_SIZE];
+    let values = [*state.button_mask as i16, state.left_stick_x, state.left_stick_y, state.right_stick_x, state.right_stick_y];
+    for (dst, v) in frame.chunks_exact_mut(2).zip(values) {
+        dst.copy_from_slice(&v.to_le_bytes());
+    }
+    frame
+}
+
+fn decode_frame(frame: &[u8]) -> GamepadState {
+    let value = |idx: usize| i16::from_le_bytes([frame[idx * 2], frame[idx * 2 + 1]]);
+
+    let bits = value(0) as u16;
+    let mut button_mask = GamepadButtonMask::none();
+    for button in BUTTONS.iter().copied().filter(|v| bits & **v != 0) {
+        button_mask.set(button);
+    }
+
+    GamepadState { button_mask, left_stick_x: value(1), left_stick_y: value(2), right_stick_x: value(3), right_stick_y: value(4) }
+}
+
+/// Records the controller's state every frame to a file, or plays a recording back in place of the controller, for reproducing bugs. <br/>
+/// Replays only line up if DOOM is timed by frame count, which it is whenever this is active
+pub enum InputReplay {
+    Off,
+    Recording { file: FileStream, path: String },
+    Playback { frames: Vec<GamepadState>, pos: usize },
+}
+
+impl InputReplay {
+    /// Start recording to the given file, or leave recording off if it can't be written (which gets logged)
+    pub fn record(path: &str) -> InputReplay {
+        match FileStream::open(path, FileMode::Write) {
+            Ok(file) => {
+                db::log(format!("Recording input to {}", path).as_str());
+                InputReplay::Recording { file, path: path.to_string() }
+            }
+            Err(e) => {
+                db::log(format!("Can't record input to {}: {:?}", path, e).as_str());
+                InputReplay::Off
+            }
+        }
+    }
+
+    /// Load a recording to play back, or leave playback off if it can't be read (which gets logged)
+    pub fn play(path: &str) -> InputReplay {
+        let bytes = match files::read_file_fully(path) {
+            Ok(v) => v,
+            Err(e) => {
+                db::log(format!("Can't play back input: {}", e).as_str());
+                return InputReplay::Off;
+            }
+        };
+
+        if bytes.len() % FRAME_SIZE != 0 {
+            db::log(format!("{}: ignoring {} bytes of incomplete frame at the end", path, bytes.len() % FRAME_SIZE).as_str());
+        }
+
+        let frames: Vec<GamepadState> = bytes.chunks_exact(FRAME_SIZE).map(decode_frame).collect();
+        db::log(format!("Playing back {} frames of input from {}", frames.len(), path).as_str());
+        InputReplay::Playback { frames, pos: 0 }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !matches!(self, InputReplay::Off)
+    }
+
+    /// Pass this frame's controller state (& the previous frame's) through the replay. While recording, it's written out & returned as-is.
+    /// While playing back, the recording's next frame is returned in its place, until it runs out & the live controller takes over again
+    pub fn apply(&mut self, state: GamepadState, prev: GamepadState) -> (GamepadState, GamepadState) {
+        match self {
+            InputReplay::Off => (state, prev),
+            InputReplay::Recording { file, path } => {
+                if let Err(e) = file.write_all(&encode_frame(state)) {
+                    db::log(format!("Failed writing input recording {}, recording stopped: {}", path, e).as_str());
+                    *self = InputReplay::Off;
+                }
+                (state, prev)
+            }
+            InputReplay::Playback { frames, pos } => {
+                let last = if *pos > 0 { Some(frames[*pos - 1]) } else { None };
+
+                if let Some(next) = frames.get(*pos) {
+                    *pos += 1;
+                    return (*next, last.unwrap_or(prev));
+                }
+
+                // the last played frame counts as the previous one, so anything it was holding registers as released
+                db::log(format!("Input playback finished after {} frames, switching to the controller", frames.len()).as_str());
+                *self = InputReplay::Off;
+                (state, last.unwrap_or(prev))
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+// DOOM mixes its sound effects at 11025 Hz.
+// DreamBox voices have no per-voice resampling quality setting - playback always uses 2-tap (linear) sampling at whatever samplerate the voice is set to,
+// and the interpolation only ever looks inside the current sample buffer (which is why process_audio has to append the next buffer's first sample to each chunk)
+// As an alternative, the chunks can be upsampled here before being submitted, so the hardware ends up stepping through the data at a much finer rate
+
+pub const DOOM_SAMPLERATE: i32 = 11025;
+
+/// How the 11025 Hz output from DOOM gets resampled to the audio hardware's rate
+#[derive(Clone, Copy, PartialEq)]
+pub enum ResampleMode {
+    /// Submit samples at 11025 Hz and let the voice's 2-tap sampling do all of the work
+    Hardware,
+    /// Linearly upsample to 44100 Hz in software before submitting
+    Linear,
+}
+
+impl ResampleMode {
+    /// Factor the sample data gets upsampled by before being submitted
+    pub fn factor(self) -> usize {
+        match self {
+            ResampleMode::Hardware => 1,
+            ResampleMode::Linear => 4,
+        }
+    }
+
+    /// Samplerate the voice should be set to for data produced by this mode
+    pub fn samplerate(self) -> i32 {
+        DOOM_SAMPLERATE * self.factor() as i32
+    }
+}
+
+/// Linearly upsample a chunk of samples by the given factor, replacing the contents of `dst`. <br/>
+/// The last sample of `src` is treated as the first sample of the following chunk, so the output interpolates all the way up to it and ends on it
+pub fn upsample_linear(src: &[i16], factor: usize, dst: &mut Vec<i16>) {
+    dst.clear();
+
+    if factor <= 1 || src.len() < 2 {
+        dst.extend_from_slice(src);
+        return;
+    }
+
+    dst.reserve((src.len() - 1) * factor + 1);
+
+    for pair in src.windows(2) {
+        let a = pair[0] as i32;
+        let b = pair[1] as i32;
+
+        for step in 0..factor as i32 {
+            dst.push((a + (b - a) * step / factor as i32) as i16);
+        }
+    }
+
+    dst.push(src[src.len() - 1]);
+}
+
+/// Scale a sample, saturating at the ends of the i16 range
+pub fn apply_gain(sample: i16, gain: f32) -> i16 {
+    (sample as f32 * gain).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// Smoothing factor for a one-pole low-pass filter with the given cutoff (in Hz), running at DOOM's samplerate
+pub fn lowpass_alpha(cutoff: f32) -> f32 {
+    1.0 - (-2.0 * std::f32::consts::PI * cutoff / DOOM_SAMPLERATE as f32).exp()
+}
+
+/// Run a one-pole low-pass filter over a chunk of samples in place. <br/>
+/// `state` is the filter's last output, which carries over from one chunk to the next so there's no step at the boundary
+pub fn lowpass(samples: &mut [i16], state: &mut f32, alpha: f32) {
+    for sample in samples {
+        *state += (*sample as f32 - *state) * alpha;
+        *sample = state.round() as i16;
+    }
+}
@@ -0,0 +1,107 @@
+// how long the controller buzzes when the fire key goes down
+const FIRE_PULSE: f32 = 0.08;
+
+// seconds of rumble per unit the screen's redness jumps by in one frame (redness runs 0-255), & the longest a damage pulse can last
+const DAMAGE_PULSE_SCALE: f32 = 0.02;
+const MAX_DAMAGE_PULSE: f32 = 0.4;
+
+// how much redder the screen has to get in one frame to count as taking damage, so small changes in the view don't set it off
+const DAMAGE_THRESHOLD: f32 = 4.0;
+
+// after a level loads, frames skipped before calibrating (so the screen melt is over) & then averaged to find the level's usual redness
+const CALIBRATION_DELAY: u32 = 60;
+const CALIBRATION_FRAMES: u32 = 8;
+
+// the framebuffer is sampled every this many pixels across & down, which is a couple hundred pixels rather than all 64000
+const SAMPLE_STEP: usize = 16;
+
+/// Approximates rumble feedback for firing & taking damage, since DOOM doesn't report either. <br/>
+/// Damage is spotted by the red pain palette washing over the screen, measured from a sparse grid of framebuffer pixels.
+/// Each level is calibrated as it starts, so a level (or palette) that's red to begin with doesn't keep setting it off
+#[derive(Default)]
+pub struct Rumble {
+    remaining: f32,
+    last_redness: Option<f32>,
+    // frames sampled since the level loaded, & their total redness, until the baseline is worked out
+    calibration_frames: u32,
+    calibration_total: f32,
+    baseline: Option<f32>,
+}
+
+impl Rumble {
+    pub fn new() -> Rumble {
+        Rumble::default()
+    }
+
+    /// Start a short pulse for the fire key being pressed
+    pub fn fire(&mut self) {
+        self.remaining = self.remaining.max(FIRE_PULSE);
+    }
+
+    /// Measure how red the given RGBA framebuffer is. If it jumped sharply since the last one & is now at least `threshold` above the level's baseline,
+    /// a pulse starts in proportion to how far above it is. Nothing pulses until the baseline has been measured
+    pub fn sample_frame(&mut self, fb: &[u8], width: usize, height: usize, threshold: f32) {
+        let mut total = 0.0;
+        let mut count = 0;
+
+        for y in (0..height).step_by(SAMPLE_STEP) {
+            for x in (0..width).step_by(SAMPLE_STEP) {
+                let px = &fb[(y * width + x) * 4..][..4];
+
+                // how far red stands out from the other channels, so bright but neutral pixels (or the gold pickup flash) don't count
+                total += px[0].saturating_sub(px[1].max(px[2])) as f32;
+                count += 1;
+            }
+        }
+
+        let redness = total / count.max(1) as f32;
+        let last = self.last_redness.replace(redness);
+
+        let baseline = match self.baseline {
+            Some(v) => v,
+            None => {
+                self.calibrate(redness);
+                return;
+            }
+        };
+
+        let excess = redness - baseline;
+        if let Some(last) = last {
+            if redness - last > DAMAGE_THRESHOLD && excess > threshold {
+                self.remaining = self.remaining.max((excess * DAMAGE_PULSE_SCALE).min(MAX_DAMAGE_PULSE));
+            }
+        }
+    }
+
+    fn calibrate(&mut self, redness: f32) {
+        self.calibration_frames += 1;
+        if self.calibration_frames <= CALIBRATION_DELAY {
+            return;
+        }
+
+        self.calibration_total += redness;
+        if self.calibration_frames == CALIBRATION_DELAY + CALIBRATION_FRAMES {
+            self.baseline = Some(self.calibration_total / CALIBRATION_FRAMES as f32);
+        }
+    }
+
+    /// Forget the baseline so it's measured again, for when a new level has loaded
+    pub fn recalibrate(&mut self) {
+        self.calibration_frames = 0;
+        self.calibration_total = 0.0;
+        self.baseline = None;
+    }
+
+    /// Stop any pulse in progress & forget the last measurement (so the next frame isn't compared against a stale one). The baseline is kept
+    pub fn reset(&mut self) {
+        self.remaining = 0.0;
+        self.last_redness = None;
+    }
+
+    /// Count down the current pulse, returning whether the motor should be running this frame
+    pub fn update(&mut self, delta: f32) -> bool {
+        let on = self.remaining > 0.0;
+        self.remaining = (self.remaining - delta).max(0.0);
+        on
+    }
+}
@@ -0,0 +1,50 @@
+use std::io::Write;
+
+use dbsdk_rs::io::{self, FileMode, FileStream};
+
+pub const SCREENSHOT_DIR: &str = "/ma/screenshots";
+
+// shotNNNN.tga
+const MAX_SCREENSHOTS: u32 = 10000;
+
+/// Encode RGBA pixels (top row first) as an uncompressed 32-bit TGA
+pub fn encode_tga(rgba: &[u8], width: u16, height: u16) -> Vec<u8> {
+    let mut data = Vec::with_capacity(18 + rgba.len());
+
+    data.extend_from_slice(&[
+        0,                      // no image ID
+        0,                      // no color map
+        2,                      // uncompressed true-color
+        0, 0, 0, 0, 0,          // color map spec (unused)
+        0, 0, 0, 0,             // x & y origin
+    ]);
+    data.extend_from_slice(&width.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.push(32);              // bits per pixel
+    data.push(0x28);            // 8 alpha bits, top-left origin
+
+    // TGA stores pixels as BGRA
+    for px in rgba.chunks_exact(4) {
+        data.extend_from_slice(&[px[2], px[1], px[0], px[3]]);
+    }
+
+    data
+}
+
+/// Path of the first screenshot number which isn't taken yet
+fn next_screenshot_path() -> Option<String> {
+    (0..MAX_SCREENSHOTS)
+        .map(|i| format!("{}/shot{:04}.tga", SCREENSHOT_DIR, i))
+        .find(|path| !io::file_exists(path))
+}
+
+/// Write RGBA pixels out as the next numbered screenshot, returning the path it was saved to
+pub fn save_screenshot(rgba: &[u8], width: u16, height: u16) -> Result<String, String> {
+    let path = next_screenshot_path().ok_or_else(|| "no free screenshot numbers left".to_string())?;
+    let data = encode_tga(rgba, width, height);
+
+    let mut file = FileStream::open(&path, FileMode::Write).map_err(|e| format!("{:?}", e))?;
+    file.write_all(&data).map_err(|e| e.to_string())?;
+
+    Ok(path)
+}
@@ -0,0 +1,92 @@
+use std::{alloc::{self, GlobalAlloc, Layout}, cell::UnsafeCell, ptr, sync::atomic::{AtomicUsize, Ordering}};
+
+// dbsdk exports its own malloc & free (for DOOM's C code), built on top of Rust's allocator. on the host, Rust's default allocator
+// is libc's malloc - which is now dbsdk's, which calls back into Rust's allocator, & so on until the stack runs out.
+// host test builds break the loop by allocating out of a fixed arena instead, which never touches malloc. nothing is ever freed,
+// which is fine for the few short tests that run
+const ARENA_SIZE: usize = 64 * 1024 * 1024;
+
+struct Arena {
+    memory: UnsafeCell<[u8;ARENA_SIZE]>,
+    used: AtomicUsize,
+}
+
+unsafe impl Sync for Arena {}
+
+unsafe impl GlobalAlloc for Arena {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let base = self.memory.get() as usize;
+        let mut used = self.used.load(Ordering::Relaxed);
+
+        loop {
+            let start = (base + used + layout.align() - 1) & !(layout.align() - 1);
+            let end = start - base + layout.size();
+            if end > ARENA_SIZE {
+                return ptr::null_mut();
+            }
+
+            match self.used.compare_exchange_weak(used, end, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return start as *mut u8,
+                Err(v) => used = v,
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+    }
+}
+
+#[global_allocator]
+static ARENA: Arena = Arena { memory: UnsafeCell::new([0;ARENA_SIZE]), used: AtomicUsize::new(0) };
+
+// dbsdk only replaces malloc & free, so libc's own realloc, calloc & posix_memalign would be handed (or hand back) blocks in the wrong format.
+// these go through dbsdk's malloc too, keeping the size in the 8 bytes in front of each block the way it does
+extern "C" {
+    fn malloc(size: i32) -> *mut u8;
+}
+
+// dbsdk's free doesn't expect a null pointer, which libc hands it all the time. build.rs lets this one replace it on the host -
+// since the arena never gives memory back anyway, there's nothing to do with the ones that aren't null either
+#[no_mangle]
+pub unsafe extern "C" fn free(_ptr: *mut u8) {
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn calloc(count: usize, size: usize) -> *mut u8 {
+    let bytes = count * size;
+    let mem = malloc(bytes as i32);
+    if !mem.is_null() {
+        ptr::write_bytes(mem, 0, bytes);
+    }
+    mem
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn realloc(old: *mut u8, size: usize) -> *mut u8 {
+    let mem = malloc(size as i32);
+    if !old.is_null() {
+        let old_size = old.sub(8).cast::<i64>().read() as usize;
+        if !mem.is_null() {
+            ptr::copy_nonoverlapping(old, mem, old_size.min(size));
+        }
+    }
+    mem
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn posix_memalign(out: *mut *mut u8, align: usize, size: usize) -> i32 {
+    // pad the front by a whole alignment so the size still fits in the 8 bytes before the block
+    let align = align.max(8);
+    let mem = match Layout::from_size_align(align + size, align) {
+        Ok(layout) => alloc::alloc(layout),
+        Err(_) => return 22,
+    };
+    if mem.is_null() {
+        return 12;
+    }
+
+    let block = mem.add(align);
+    block.sub(8).cast::<i64>().write((align + size - 8) as i64);
+    *out = block;
+    0
+}
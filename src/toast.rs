@@ -0,0 +1,60 @@
+use std::collections::VecDeque;
+
+use dbsdk_rs::vdp::Color32;
+
+use overlay::{self, Overlay};
+
+// most toasts on screen at once - showing another pushes out the oldest
+const MAX_TOASTS: usize = 4;
+
+// toasts fade out over the last part of their lifetime
+const FADE_TIME: f32 = 0.5;
+
+const TOAST_SCALE: f32 = 2.0;
+const TOAST_MARGIN: f32 = 16.0;
+
+struct Toast {
+    text: String,
+    remaining: f32,
+}
+
+/// Short messages which are shown stacked at the top of the screen for a few seconds each, newest at the bottom
+#[derive(Default)]
+pub struct ToastQueue {
+    toasts: VecDeque<Toast>,
+}
+
+impl ToastQueue {
+    pub fn new() -> ToastQueue {
+        ToastQueue::default()
+    }
+
+    pub fn push(&mut self, text: &str, seconds: f32) {
+        if self.toasts.len() >= MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+
+        self.toasts.push_back(Toast { text: text.to_string(), remaining: seconds });
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        for toast in &mut self.toasts {
+            toast.remaining -= delta;
+        }
+
+        self.toasts.retain(|v| v.remaining > 0.0);
+    }
+
+    pub fn draw(&self, overlay: &mut Overlay) {
+        let line_spacing = overlay::LINE_HEIGHT * TOAST_SCALE + 8.0;
+
+        for (i, toast) in self.toasts.iter().enumerate() {
+            let alpha = (toast.remaining / FADE_TIME).min(1.0);
+            let width = Overlay::text_width(&toast.text, TOAST_SCALE) + 16.0;
+            let y = TOAST_MARGIN + line_spacing * i as f32;
+
+            overlay.draw_rect(((overlay::SCREEN_WIDTH - width) * 0.5).floor(), y - 4.0, width, line_spacing - 4.0, Color32::new(0, 0, 0, (160.0 * alpha) as u8));
+            overlay.draw_text_centered(y, TOAST_SCALE, &toast.text, Color32::new(255, 255, 255, (255.0 * alpha) as u8));
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::convert::TryInto;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IMA_ADPCM: u16 = 0x11;
+
+/// Sample data from a WAV file, in one of the encodings dbsdk can play directly
+pub enum WavData {
+    Pcm16(Vec<i16>),
+    /// IMA ADPCM blocks, each `block_size` bytes long
+    ImaAdpcm { data: Vec<u8>, block_size: i32 },
+}
+
+/// A mono WAV file
+pub struct Wav {
+    pub samplerate: i32,
+    pub data: WavData,
+    /// Length in samples
+    pub length: usize,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Parse a mono WAV file, either 16-bit PCM or IMA ADPCM
+pub fn parse_wav(bytes: &[u8]) -> Result<Wav, String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a WAV file".to_string());
+    }
+
+    let mut format = None;
+    let mut sample_data = None;
+    let mut fact_length = None;
+
+    // walk the chunk list - chunks are padded to an even length. a chunk claiming to run past the end of the file is cut short,
+    // & one big enough to overflow the offset ends the walk
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = read_u32(bytes, offset + 4) as usize;
+        let body = &bytes[offset + 8..offset + 8 + size.min(bytes.len() - offset - 8)];
+
+        match id {
+            b"fmt " if body.len() >= 16 => format = Some(body),
+            b"data" => sample_data = Some(body),
+            b"fact" if body.len() >= 4 => fact_length = Some(read_u32(body, 0) as usize),
+            _ => {}
+        }
+
+        match size.checked_add(8 + (size & 1)).and_then(|v| offset.checked_add(v)) {
+            Some(next) => offset = next,
+            None => break,
+        }
+    }
+
+    let format = format.ok_or_else(|| "missing fmt chunk".to_string())?;
+    let sample_data = sample_data.ok_or_else(|| "missing data chunk".to_string())?;
+
+    let format_tag = read_u16(format, 0);
+    let channels = read_u16(format, 2);
+    let samplerate = read_u32(format, 4) as i32;
+    let block_size = read_u16(format, 12) as usize;
+    let bits = read_u16(format, 14);
+
+    if channels != 1 {
+        return Err(format!("expected a mono file, got {} channels", channels));
+    }
+
+    match (format_tag, bits) {
+        (FORMAT_PCM, 16) => {
+            let samples: Vec<i16> = sample_data.chunks_exact(2).map(|v| i16::from_le_bytes([v[0], v[1]])).collect();
+            let length = samples.len();
+            Ok(Wav { samplerate, data: WavData::Pcm16(samples), length })
+        }
+        (FORMAT_IMA_ADPCM, 4) => {
+            if block_size <= 4 {
+                return Err(format!("bad ADPCM block size {}", block_size));
+            }
+
+            // each block starts with a 4 byte header holding its first sample, followed by two samples per byte.
+            // the fact chunk gives the exact length, since the last block is usually only partly used
+            let samples_per_block = (block_size - 4) * 2 + 1;
+            let length = fact_length.unwrap_or(sample_data.len() / block_size * samples_per_block);
+            Ok(Wav { samplerate, data: WavData::ImaAdpcm { data: sample_data.to_vec(), block_size: block_size as i32 }, length })
+        }
+        _ => Err(format!("unsupported format {} ({} bits), expected 16-bit PCM or IMA ADPCM", format_tag, bits)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], size: u32, body: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&size.to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    /// A mono 16-bit PCM file, followed by whatever extra chunks are given
+    fn pcm_wav(samples: &[i16], data_size: u32, extra: &[u8]) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&FORMAT_PCM.to_le_bytes());
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&11025u32.to_le_bytes());
+        fmt.extend_from_slice(&22050u32.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+
+        let data: Vec<u8> = samples.iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend(chunk(b"fmt ", fmt.len() as u32, &fmt));
+        bytes.extend_from_slice(extra);
+        bytes.extend(chunk(b"data", data_size, &data));
+        bytes
+    }
+
+    fn pcm_samples(wav: &Wav) -> &[i16] {
+        match &wav.data {
+            WavData::Pcm16(samples) => samples,
+            _ => panic!("expected PCM data"),
+        }
+    }
+
+    #[test]
+    fn parses_pcm() {
+        let wav = parse_wav(&pcm_wav(&[1, -2, 3], 6, &[])).unwrap();
+        assert_eq!(wav.samplerate, 11025);
+        assert_eq!(pcm_samples(&wav), &[1, -2, 3]);
+    }
+
+    #[test]
+    fn truncated_data_chunk_keeps_what_is_there() {
+        let wav = parse_wav(&pcm_wav(&[1, -2, 3], 1000, &[])).unwrap();
+        assert_eq!(pcm_samples(&wav), &[1, -2, 3]);
+    }
+
+    #[test]
+    fn oversized_chunk_ends_the_walk() {
+        let wav = parse_wav(&pcm_wav(&[4, 5], u32::MAX, &[])).unwrap();
+        assert_eq!(pcm_samples(&wav), &[4, 5]);
+
+        // an oversized chunk before the data hides it rather than running off the end
+        let extra = chunk(b"LIST", u32::MAX, &[0; 4]);
+        assert!(parse_wav(&pcm_wav(&[4, 5], 4, &extra)).is_err());
+    }
+}